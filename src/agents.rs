@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    hexmap::{HexMap, HexPos},
+    schedule::{SurfaceStage, SurfaceSystem},
+    simulation::{MyTileData, TileKind},
+    surfaces::Surfaces,
+};
+
+/// How far up an agent can climb in a single step
+const MAX_CLIMB: u8 = 1;
+/// How far down an agent can drop in a single step
+const MAX_DROP: u8 = 2;
+
+/// Something that lives on a hex in a surface world, always paired with a `HexPos` component
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Agent {
+    /// Ticks between steps
+    pub move_every: u16,
+    pub cooldown: u16,
+}
+
+/// Where an agent is walking to, removed once it gets there
+#[derive(Component, Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Destination(pub HexPos);
+
+/// Which agents are on which hex. Rebuilt at the start of every tick and kept up to date by
+/// `move_agents` so later systems can ask "who is on this hex" without scanning every agent.
+// Not Inspectable because of HashMap
+#[derive(Debug, Default, Clone)]
+pub struct AgentIndex {
+    by_hex: HashMap<HexPos, Vec<Entity>>,
+}
+
+impl AgentIndex {
+    pub fn on(&self, pos: HexPos) -> &[Entity] {
+        self.by_hex.get(&pos).map_or(&[], |agents| agents)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (HexPos, Entity)> + '_ {
+        self.by_hex
+            .iter()
+            .flat_map(|(&pos, agents)| agents.iter().map(move |&agent| (pos, agent)))
+    }
+
+    fn insert(&mut self, pos: HexPos, agent: Entity) {
+        self.by_hex.entry(pos).or_default().push(agent);
+    }
+
+    fn remove(&mut self, pos: HexPos, agent: Entity) {
+        if let Some(agents) = self.by_hex.get_mut(&pos) {
+            agents.retain(|&other| other != agent);
+            if agents.is_empty() {
+                self.by_hex.remove(&pos);
+            }
+        }
+    }
+}
+
+// by hand so the entities keep their generations, see `snapshot::entity_bits`
+impl Serialize for AgentIndex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().map(|(pos, agent)| (pos, agent.to_bits())))
+    }
+}
+
+impl<'de> Deserialize<'de> for AgentIndex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut index = Self::default();
+        for (pos, agent) in Vec::<(HexPos, u64)>::deserialize(deserializer)? {
+            index.insert(pos, Entity::from_bits(agent));
+        }
+        Ok(index)
+    }
+}
+
+/// Whether an agent standing on `from` can step onto `to`
+pub fn can_step(from: &MyTileData, to: &MyTileData) -> bool {
+    to.kind != TileKind::Water
+        && !to.fire.is_burning()
+        && !to.lava.is_molten()
+        && to.height <= from.height.saturating_add(MAX_CLIMB)
+        && to.height >= from.height.saturating_sub(MAX_DROP)
+}
+
+pub fn spawn_agent(world: &mut World, pos: HexPos) -> Entity {
+    world
+        .spawn()
+        .insert_bundle((
+            Agent {
+                move_every: 10,
+                cooldown: 0,
+            },
+            pos,
+        ))
+        .id()
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| world.init_resource::<AgentIndex>())
+        .push_state_resource::<AgentIndex>()
+        .push_state_component::<Agent>()
+        .push_state_component::<Destination>()
+        .push_system(SurfaceSystem::new(index_agents).in_stage(SurfaceStage::PreSim))
+        .push_system(move_agents);
+}
+
+fn index_agents(mut index: ResMut<AgentIndex>, agents: Query<(Entity, &HexPos), With<Agent>>) {
+    index.by_hex.clear();
+    for (agent, &pos) in agents.iter() {
+        index.insert(pos, agent);
+    }
+}
+
+fn move_agents(
+    mut cmds: Commands<'_, '_>,
+    map: Res<HexMap<MyTileData>>,
+    mut index: ResMut<AgentIndex>,
+    mut agents: Query<(Entity, &mut Agent, &mut HexPos, &Destination)>,
+) {
+    for (entity, mut agent, mut pos, destination) in agents.iter_mut() {
+        if agent.cooldown > 0 {
+            agent.cooldown -= 1;
+            continue;
+        }
+
+        let destination = map.wrap(destination.0);
+        if *pos == destination {
+            cmds.entity(entity).remove::<Destination>();
+            continue;
+        }
+
+        // greedy step, good enough until agents get proper pathfinding
+        let here = map.get(*pos);
+        let current_distance = map.wrapped_distance(*pos, destination);
+        let step = map
+            .wrapped_neighbors(*pos)
+            .filter(|&next| can_step(here, map.get(next)))
+            .map(|next| (map.wrapped_distance(next, destination), next))
+            .filter(|&(distance, _)| distance < current_distance)
+            .min_by_key(|&(distance, _)| distance);
+
+        match step {
+            Some((_, next)) => {
+                index.remove(*pos, entity);
+                index.insert(next, entity);
+                *pos = next;
+                agent.cooldown = agent.move_every;
+            }
+            // stuck, give up rather than trying every tick
+            None => {
+                cmds.entity(entity).remove::<Destination>();
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{bevy_egui::EguiContext, egui, Inspectable};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agents::{Agent, AgentIndex, Destination},
+    flowfield::{FlowGoal, GoalSet},
+    hexmap::{HexMap, HexPos},
+    influence::{InfluenceMap, Threat},
+    schedule::SurfaceSystem,
+    simulation::{MyTileData, TileKind},
+    surfaces::{CurrentSurface, Surfaces},
+    AppState,
+};
+
+/// How far agents look when searching for water/food/danger
+const SEARCH_RADIUS: u32 = 8;
+const DANGER_RADIUS: u32 = 3;
+
+/// Per tick, needs go from `0.0` (satisfied) up to `1.0` (desperate)
+const THIRST_PER_TICK: f32 = 0.001;
+const HUNGER_PER_TICK: f32 = 0.0005;
+const FATIGUE_PER_TICK: f32 = 0.0005;
+
+#[derive(Component, Debug, Default, Clone, Inspectable, Serialize, Deserialize)]
+pub struct Needs {
+    pub thirst: f32,
+    pub hunger: f32,
+    pub fatigue: f32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Inspectable, Serialize, Deserialize)]
+pub enum AgentAction {
+    MoveTowardWater,
+    Gather,
+    Flee,
+    Rest,
+}
+
+/// Scores from the last time this agent thought about what to do, kept around for the debug view
+#[derive(Component, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Brain {
+    pub scores: Vec<(AgentAction, f32)>,
+    pub chosen: Option<AgentAction>,
+}
+
+/// Everything a consideration is allowed to look at
+pub struct AiContext<'a> {
+    pub agent: Entity,
+    pub pos: HexPos,
+    pub needs: &'a Needs,
+    pub map: &'a HexMap<MyTileData>,
+    pub index: &'a AgentIndex,
+    pub threat: &'a InfluenceMap<Threat>,
+}
+
+impl AiContext<'_> {
+    /// Closest tile within `SEARCH_RADIUS` steps matching `pred`, and how far away it is
+    pub fn nearest(&self, pred: impl Fn(HexPos, &MyTileData) -> bool) -> Option<(HexPos, u32)> {
+        nearest(self.map, self.pos, SEARCH_RADIUS, pred)
+    }
+
+    /// Other agents on this hex or the ones next to it
+    pub fn nearby_agents(&self) -> usize {
+        std::iter::once(self.pos)
+            .chain(self.map.wrapped_neighbors(self.pos))
+            .flat_map(|pos| self.index.on(pos))
+            .filter(|&&other| other != self.agent)
+            .count()
+    }
+}
+
+/// Breadth first search outwards from `from`, wrapping around the map edges
+pub fn nearest(
+    map: &HexMap<MyTileData>,
+    from: HexPos,
+    max_distance: u32,
+    pred: impl Fn(HexPos, &MyTileData) -> bool,
+) -> Option<(HexPos, u32)> {
+    let mut seen = HashSet::from([from]);
+    let mut queue = VecDeque::from([(from, 0)]);
+    while let Some((pos, distance)) = queue.pop_front() {
+        if pred(pos, map.get(pos)) {
+            return Some((pos, distance));
+        }
+        if distance == max_distance {
+            continue;
+        }
+        for next in map.wrapped_neighbors(pos) {
+            if seen.insert(next) {
+                queue.push_back((next, distance + 1));
+            }
+        }
+    }
+    None
+}
+
+/// Scores how much one aspect of the situation favours an action, `0.0..=1.0`
+pub type Consideration = fn(&AiContext<'_>) -> f32;
+
+pub struct ActionScorer {
+    pub action: AgentAction,
+    /// Multiplied together, so any one of them can veto the action by returning `0.0`
+    pub considerations: Vec<Consideration>,
+}
+
+impl ActionScorer {
+    pub fn score(&self, cx: &AiContext<'_>) -> f32 {
+        self.considerations
+            .iter()
+            .map(|consideration| consideration(cx).clamp(0.0, 1.0))
+            .product()
+    }
+}
+
+/// The set of actions agents on a surface pick between, a resource so surfaces can use different rules
+pub struct UtilityAi {
+    pub scorers: Vec<ActionScorer>,
+}
+
+impl Default for UtilityAi {
+    fn default() -> Self {
+        Self {
+            scorers: vec![
+                ActionScorer {
+                    action: AgentAction::MoveTowardWater,
+                    considerations: vec![|cx| cx.needs.thirst, water_nearby],
+                },
+                ActionScorer {
+                    action: AgentAction::Gather,
+                    considerations: vec![|cx| cx.needs.hunger, food_nearby],
+                },
+                ActionScorer {
+                    action: AgentAction::Flee,
+                    considerations: vec![danger],
+                },
+                ActionScorer {
+                    action: AgentAction::Rest,
+                    considerations: vec![|cx| cx.needs.fatigue, |cx| 1.0 - danger(cx)],
+                },
+            ],
+        }
+    }
+}
+
+fn is_shore(map: &HexMap<MyTileData>, pos: HexPos) -> bool {
+    map.get(pos).kind != TileKind::Water
+        && map
+            .wrapped_neighbors(pos)
+            .any(|pos| map.get(pos).kind == TileKind::Water)
+}
+
+fn has_food(tile: &MyTileData) -> bool {
+    tile.vegetation.density > 0.1
+}
+
+fn distance_falloff(distance: u32, max: u32) -> f32 {
+    1.0 - distance as f32 / (max + 1) as f32
+}
+
+fn water_nearby(cx: &AiContext<'_>) -> f32 {
+    cx.nearest(|pos, _| is_shore(cx.map, pos))
+        .map_or(0.0, |(_, distance)| {
+            distance_falloff(distance, SEARCH_RADIUS)
+        })
+}
+
+fn food_nearby(cx: &AiContext<'_>) -> f32 {
+    cx.nearest(|_, tile| has_food(tile))
+        .map_or(0.0, |(_, distance)| {
+            distance_falloff(distance, SEARCH_RADIUS)
+        })
+}
+
+/// Threat (i.e. fire) close by, or being packed in with lots of other agents
+fn danger(cx: &AiContext<'_>) -> f32 {
+    let crowding = cx.nearby_agents() as f32 / 12.0;
+    cx.threat.get(cx.pos).max(crowding)
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+enum AiSystem {
+    UpdateNeeds,
+    Score,
+}
+
+pub fn init_app(app: &mut App) {
+    app.add_system(agent_ai_debug_window.run_in_state(AppState::Playing));
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| world.init_resource::<UtilityAi>())
+        .push_state_component::<Needs>()
+        .push_state_component::<Brain>()
+        .push_system(attach_brains)
+        .push_system(SurfaceSystem::new(update_needs).label(AiSystem::UpdateNeeds))
+        .push_system(
+            SurfaceSystem::new(score_actions)
+                .label(AiSystem::Score)
+                .after(AiSystem::UpdateNeeds),
+        )
+        .push_system(SurfaceSystem::new(execute_actions).after(AiSystem::Score));
+}
+
+fn attach_brains(mut cmds: Commands<'_, '_>, agents: Query<Entity, (With<Agent>, Without<Brain>)>) {
+    for agent in agents.iter() {
+        cmds.entity(agent)
+            .insert_bundle((Needs::default(), Brain::default()));
+    }
+}
+
+fn update_needs(mut agents: Query<&mut Needs>) {
+    for mut needs in agents.iter_mut() {
+        needs.thirst = (needs.thirst + THIRST_PER_TICK).min(1.0);
+        needs.hunger = (needs.hunger + HUNGER_PER_TICK).min(1.0);
+        needs.fatigue = (needs.fatigue + FATIGUE_PER_TICK).min(1.0);
+    }
+}
+
+fn score_actions(
+    ai: Res<UtilityAi>,
+    map: Res<HexMap<MyTileData>>,
+    index: Res<AgentIndex>,
+    threat: Res<InfluenceMap<Threat>>,
+    mut agents: Query<(Entity, &HexPos, &Needs, &mut Brain)>,
+) {
+    for (agent, &pos, needs, mut brain) in agents.iter_mut() {
+        let cx = AiContext {
+            agent,
+            pos,
+            needs,
+            map: &map,
+            index: &index,
+            threat: &threat,
+        };
+        brain.scores = ai
+            .scorers
+            .iter()
+            .map(|scorer| (scorer.action, scorer.score(&cx)))
+            .collect();
+        // on ties the earlier scorer wins, `max_by` would pick the later one. Nothing scoring
+        // above zero means the agent has nothing worth doing and just idles.
+        brain.chosen = brain
+            .scores
+            .iter()
+            .fold(
+                None,
+                |best: Option<(AgentAction, f32)>, &(action, score)| match best {
+                    Some((_, best_score)) if best_score >= score => best,
+                    _ if score <= 0.0 => best,
+                    _ => Some((action, score)),
+                },
+            )
+            .map(|(action, _)| action);
+    }
+}
+
+fn execute_actions(
+    mut cmds: Commands<'_, '_>,
+    mut map: ResMut<HexMap<MyTileData>>,
+    threat: Res<InfluenceMap<Threat>>,
+    mut agents: Query<(Entity, &HexPos, &mut Needs, &Brain, Option<&Destination>)>,
+    following: Query<(), With<FlowGoal>>,
+) {
+    // every thirsty agent follows the same flow field to the nearest shore
+    let mut shores = None;
+    for (agent, &pos, mut needs, brain, destination) in agents.iter_mut() {
+        let walking = destination.is_some() || following.contains(agent);
+        match brain.chosen {
+            Some(AgentAction::MoveTowardWater) if is_shore(&map, pos) => needs.thirst = 0.0,
+            Some(AgentAction::MoveTowardWater) if !walking => {
+                let shores = shores.get_or_insert_with(|| {
+                    GoalSet::new(map.positions().filter(|&pos| is_shore(&map, pos)))
+                });
+                if !shores.goals().is_empty() {
+                    cmds.entity(agent).insert(FlowGoal(shores.clone()));
+                }
+            }
+            Some(AgentAction::Gather) if has_food(map.get(pos)) => {
+                needs.hunger = (needs.hunger - 0.05).max(0.0);
+                map.get_mut(pos).vegetation.density -= 0.01;
+            }
+            Some(AgentAction::Gather) if !walking => {
+                if let Some((food, _)) = nearest(&map, pos, SEARCH_RADIUS, |_, tile| has_food(tile))
+                {
+                    cmds.entity(agent).insert(Destination(food));
+                }
+            }
+            Some(AgentAction::Flee) => {
+                // head for the safest hex nearby, or just step anywhere to get out of a crowd
+                let safest = threat.safest_within(pos, DANGER_RADIUS);
+                let away = match safest == pos {
+                    true => map.wrapped_neighbors(pos).last(),
+                    false => Some(safest),
+                };
+                if let Some(away) = away {
+                    cmds.entity(agent)
+                        .remove::<FlowGoal>()
+                        .insert(Destination(away));
+                }
+            }
+            Some(AgentAction::Rest) => {
+                needs.fatigue = (needs.fatigue - 0.01).max(0.0);
+                cmds.entity(agent)
+                    .remove::<Destination>()
+                    .remove::<FlowGoal>();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Shows what every agent on the selected surface is thinking
+fn agent_ai_debug_window(mut egui_ctx: ResMut<EguiContext>, mut surface: CurrentSurface<'_, '_>) {
+    let Some(world) = surface.world_mut() else {
+        return;
+    };
+    let mut agents = world.query::<(Entity, &HexPos, &Needs, &Brain)>();
+
+    egui::Window::new("Agent AI").show(egui_ctx.ctx_mut(), |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (agent, pos, needs, brain) in agents.iter(world) {
+                ui.collapsing(format!("{agent:?} at ({}, {})", pos.q, pos.r), |ui| {
+                    ui.label(format!(
+                        "thirst {:.2}  hunger {:.2}  fatigue {:.2}",
+                        needs.thirst, needs.hunger, needs.fatigue
+                    ));
+                    for &(action, score) in brain.scores.iter() {
+                        let marker = match brain.chosen == Some(action) {
+                            true => ">",
+                            false => " ",
+                        };
+                        ui.monospace(format!("{marker} {action:?}: {score:.3}"));
+                    }
+                });
+            }
+        });
+    });
+}
@@ -0,0 +1,149 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::{bevy_egui::EguiContext, egui, Inspectable};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hexmap::HexMap,
+    schedule::SurfaceSystem,
+    simulation::{MyTileData, TileKind},
+    surfaces::{SelectedSurface, SurfaceTick, Surfaces},
+    vegetation, AppState,
+};
+
+/// How much warmer mid summer is than the yearly average, and mid winter colder
+const SEASONAL_SWING: f32 = 0.3;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Inspectable, Serialize, Deserialize)]
+pub enum Season {
+    #[default]
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+impl Season {
+    pub const ALL: [Self; 4] = [Self::Spring, Self::Summer, Self::Autumn, Self::Winter];
+
+    /// Multiplies how fast vegetation grows
+    pub fn growth_factor(self) -> f32 {
+        match self {
+            Self::Spring => 1.5,
+            Self::Summer => 1.0,
+            Self::Autumn => 0.5,
+            Self::Winter => 0.1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Inspectable)]
+pub struct Date {
+    pub year: u64,
+    pub season: Season,
+    /// Day of the season, starting from `0`
+    pub day: u32,
+}
+
+/// Maps a surface's ticks onto days, seasons and years, every year starts at the start of spring
+#[derive(Copy, Clone, Debug, Inspectable, Serialize, Deserialize)]
+pub struct Calendar {
+    pub ticks_per_day: u32,
+    pub days_per_season: u32,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Self {
+            ticks_per_day: 24,
+            days_per_season: 30,
+        }
+    }
+}
+
+impl Calendar {
+    /// At least 1 even if the calendar has been set to 0 ticks per day or days per season
+    pub fn ticks_per_season(&self) -> u64 {
+        (self.ticks_per_day as u64 * self.days_per_season as u64).max(1)
+    }
+
+    pub fn ticks_per_year(&self) -> u64 {
+        (self.ticks_per_season() * Season::ALL.len() as u64).max(1)
+    }
+
+    pub fn date(&self, tick: u64) -> Date {
+        let season_tick = tick % self.ticks_per_year();
+        Date {
+            year: tick / self.ticks_per_year(),
+            season: Season::ALL[(season_tick / self.ticks_per_season()) as usize],
+            day: ((season_tick % self.ticks_per_season()) / self.ticks_per_day.max(1) as u64)
+                as u32,
+        }
+    }
+
+    /// How far through the year `tick` is, `0.0..1.0`
+    pub fn year_fraction(&self, tick: u64) -> f32 {
+        (tick % self.ticks_per_year()) as f32 / self.ticks_per_year() as f32
+    }
+
+    /// `1.0` in the middle of summer, `-1.0` in the middle of winter, changing smoothly in between
+    pub fn summer_factor(&self, tick: u64) -> f32 {
+        (std::f32::consts::TAU * (self.year_fraction(tick) - 0.125)).sin()
+    }
+
+    /// Added to `vegetation::temperature`
+    pub fn temperature_offset(&self, tick: u64) -> f32 {
+        SEASONAL_SWING * self.summer_factor(tick)
+    }
+}
+
+pub fn init_app(app: &mut App) {
+    app.add_system(date_window.run_in_state(AppState::Playing));
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| world.init_resource::<Calendar>())
+        .push_state_resource::<Calendar>()
+        .push_system(SurfaceSystem::new(update_snow).when_dormant());
+}
+
+/// Snow settles on land that's below freezing and melts once it warms up
+fn update_snow(
+    mut map: ResMut<HexMap<MyTileData>>,
+    calendar: Res<Calendar>,
+    tick: Res<SurfaceTick>,
+) {
+    let offset = calendar.temperature_offset(tick.0);
+    for pos in map.positions() {
+        let tile = map.get(pos);
+        let snow = tile.kind == TileKind::Rock
+            && !tile.lava.is_molten()
+            && vegetation::temperature(tile, offset) <= 0.0;
+        if tile.snow != snow {
+            map.get_mut(pos).snow = snow;
+        }
+    }
+}
+
+fn date_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    surfaces: Res<Surfaces>,
+    selected: Res<SelectedSurface>,
+) {
+    let Some(world) = surfaces.get(selected.0) else {
+        return;
+    };
+    let date = world
+        .resource::<Calendar>()
+        .date(world.resource::<SurfaceTick>().0);
+
+    egui::Window::new("Calendar").show(egui_ctx.ctx_mut(), |ui| {
+        ui.label(format!(
+            "Year {}, {:?}, day {}",
+            date.year + 1,
+            date.season,
+            date.day + 1
+        ));
+    });
+}
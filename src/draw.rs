@@ -1,7 +1,16 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    hexmap::HexPos, loading::HexObjectAsset, simulation::TileKind, surfaces::CurrentHexMap,
+    agents::AgentIndex,
+    calendar::Calendar,
+    hexmap::{HexMap, HexPos},
+    loading::HexObjectAsset,
+    sealevel::SeaLevel,
+    settlements::Settlement,
+    simulation::{MyTileData, SurfaceTileChanged},
+    surfaces::{CurrentHexMap, SelectedSurface, SurfaceId, SurfaceTick, Surfaces},
+    vegetation::{PlantKind, Vegetation},
+    wildfire::Fire,
     AppState,
 };
 use bevy::{
@@ -26,6 +35,12 @@ const HEX_TALLNESS: f32 = 20.0;
 const HEX_WIDTH: f32 = 40.0;
 const HEX_HEIGHT: f32 = 34.0;
 const HEX_HORIZ_SPACING: f32 = 30.0;
+/// Top face of the glTF hex mesh before it gets scaled by `HEX_SCALAR`
+const HEX_MESH_TOP: f32 = 0.6;
+/// How much higher the sun is in mid summer (and lower in mid winter), radians
+const SUN_SEASONAL_SWING: f32 = 0.25;
+/// Big enough to cover the screen at any zoom we allow
+const WATER_PLANE_SIZE: f32 = 8192.0;
 
 #[derive(Component, Copy, Clone, Eq, PartialEq, Debug, Inspectable)]
 struct RenderTileEntity {
@@ -38,9 +53,100 @@ struct WindowSize(f32, f32);
 
 struct MyRaycastSet;
 
+/// The hex under the cursor, wrapped onto the map
+#[derive(Debug, Default, Inspectable)]
+pub struct HoveredHex(pub Option<HexPos>);
+
+/// Main world stand-in for an agent or settlement living in a surface world
+#[derive(Component, Debug, Inspectable)]
+struct SurfaceMirror {
+    surface: SurfaceId,
+    entity: Entity,
+}
+
+/// Drawn at the selected surface's current `SeaLevel`, following the camera around
+#[derive(Component, Debug, Inspectable)]
+struct WaterPlane;
+
+#[derive(Copy, Clone)]
+enum MirrorKind {
+    Agent,
+    Settlement,
+}
+
+struct MirrorVisuals {
+    agent_mesh: Handle<Mesh>,
+    agent_material: Handle<StandardMaterial>,
+    settlement_mesh: Handle<Mesh>,
+    settlement_material: Handle<StandardMaterial>,
+}
+
+/// Materials for tile states that don't come from the glTF
+struct TileMaterials {
+    /// Vegetation tinted copies of the glTF materials, keyed by base material, plant and density bucket
+    tinted: HashMap<(Handle<StandardMaterial>, PlantKind, u8), Handle<StandardMaterial>>,
+    burning: Handle<StandardMaterial>,
+    ash: Handle<StandardMaterial>,
+    lava: Handle<StandardMaterial>,
+    snow: Handle<StandardMaterial>,
+}
+
+/// How many distinct tints are used for vegetation density, so we don't make a material per tile
+const VEGETATION_TINT_STEPS: f32 = 4.0;
+
 pub fn init_app(app: &mut App) {
     app.add_plugin(InputManagerPlugin::<Action>::default());
     app.add_plugin(DefaultRaycastingPlugin::<MyRaycastSet>::default());
+    app.init_resource::<HoveredHex>();
+    app.add_startup_system(
+        |mut cmds: Commands<'_, '_>,
+         mut meshes: ResMut<Assets<Mesh>>,
+         mut materials: ResMut<Assets<StandardMaterial>>| {
+            cmds.insert_resource(MirrorVisuals {
+                agent_mesh: meshes.add(Mesh::from(shape::Icosphere {
+                    radius: 6.0,
+                    subdivisions: 2,
+                })),
+                agent_material: materials.add(Color::rgb(0.85, 0.2, 0.6).into()),
+                settlement_mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+                settlement_material: materials.add(Color::rgb(0.9, 0.8, 0.55).into()),
+            });
+            cmds.insert_resource(TileMaterials {
+                tinted: HashMap::new(),
+                burning: materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.9, 0.3, 0.05),
+                    emissive: Color::rgb(1.0, 0.45, 0.0),
+                    ..default()
+                }),
+                ash: materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.2, 0.2, 0.2),
+                    perceptual_roughness: 1.0,
+                    ..default()
+                }),
+                lava: materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.7, 0.1, 0.0),
+                    emissive: Color::rgb(1.0, 0.25, 0.0),
+                    ..default()
+                }),
+                snow: materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.95, 0.95, 1.0),
+                    perceptual_roughness: 0.9,
+                    ..default()
+                }),
+            });
+            cmds.spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::splat(WATER_PLANE_SIZE)))),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::rgba(0.1, 0.35, 0.7, 0.6),
+                    alpha_mode: AlphaMode::Blend,
+                    perceptual_roughness: 0.2,
+                    ..default()
+                }),
+                ..default()
+            })
+            .insert(WaterPlane);
+        },
+    );
     app.add_startup_system(|mut cmds: Commands<'_, '_>, windows: Res<Windows>| {
         let window = windows.get_primary().unwrap();
         cmds.insert_resource(WindowSize(window.width(), window.height()));
@@ -52,12 +158,7 @@ pub fn init_app(app: &mut App) {
     app.add_startup_system(|mut cmds: Commands<'_, '_>| {
         const HALF_SIZE: f32 = 1.0; // TODO: learn about the magic of this magic number
         cmds.spawn_bundle(DirectionalLightBundle {
-            transform: Transform::default().with_rotation(Quat::from_euler(
-                EulerRot::ZYX,
-                0.0,
-                2.0 as f32 * std::f32::consts::TAU / 10.0,
-                -std::f32::consts::FRAC_PI_4,
-            )),
+            transform: Transform::default().with_rotation(sun_rotation(0.0)),
             directional_light: DirectionalLight {
                 shadow_projection: OrthographicProjection {
                     left: -HALF_SIZE,
@@ -86,13 +187,28 @@ pub fn init_app(app: &mut App) {
             .run_in_state(AppState::Playing)
             .after(UpdateCameraPos),
     )
+    .add_system(
+        mirror_surface_entities
+            .run_in_state(AppState::Playing)
+            .after(UpdateCameraPos),
+    )
+    .add_system(update_sun.run_in_state(AppState::Playing))
+    .add_system(
+        update_water_plane
+            .run_in_state(AppState::Playing)
+            .after(UpdateCameraPos),
+    )
     // FIXME this ought to be AppState::Playing but no instant commands Sigh bevy
     .add_enter_system(AppState::Loading, default_camera);
 }
 
 #[derive(Actionlike, Copy, Clone, Debug, Inspectable)]
-enum Action {
+pub(crate) enum Action {
     MoveCamera,
+    IgniteHex,
+    Erupt,
+    Save,
+    Load,
 }
 
 fn default_camera(mut cmds: Commands<'_, '_>) {
@@ -126,90 +242,115 @@ fn default_camera(mut cmds: Commands<'_, '_>) {
                 },
                 Action::MoveCamera,
             )
+            .insert(KeyCode::F, Action::IgniteHex)
+            .insert(MouseButton::Right, Action::Erupt)
+            .insert(KeyCode::F5, Action::Save)
+            .insert(KeyCode::F9, Action::Load)
             .build(),
     })
     .insert(RayCastSource::<MyRaycastSet>::new());
 }
 
-// Helper for outlining an area to be hexified/covered in hex visuals
-#[derive(Debug, Inspectable)]
-struct HexRect {
-    q: i32,
-    r: i32,
-    width: i32,
-    height: i32,
-}
-
-impl HexRect {
-    fn new(q: i32, r: i32, width: i32, height: i32) -> HexRect {
-        HexRect {
-            q,
-            r,
-            width,
-            height,
-        }
-    }
-
-    // Note: inclusive
-    fn is_in_rect(&self, q: i32, r: i32) -> bool {
-        q >= self.q && q <= self.q + self.width && r >= self.r && r <= self.r + self.height
-    }
-
-    // Adds padding around the rect to ensure hexes are drawn just
-    // offscreen to prevent visual glitches when moving quickly
-    fn is_in_padded_rect(&self, q: i32, r: i32, padding: i32) -> bool {
-        let padded_rect = HexRect::new(
-            self.q - padding,
-            self.r - padding,
-            self.width + padding,
-            self.height + padding,
-        );
-        padded_rect.is_in_rect(q, r)
-    }
-}
-
 fn create_hex_visual(
     selected: bool,
-    tile_kind: TileKind,
+    tile: &MyTileData,
     hex_object_asset: &HexObjectAsset,
     assets_gltf: &Assets<Gltf>,
     assets_gltfmesh: &Assets<GltfMesh>,
+    materials: &mut Assets<StandardMaterial>,
+    tile_materials: &mut TileMaterials,
 ) -> (Handle<Mesh>, Handle<StandardMaterial>) {
     // (unwrap safety: we know the GLTF has loaded already)
     let gltf = assets_gltf.get(&hex_object_asset.0).unwrap();
-    let hex_visual = assets_gltfmesh
-        .get(&gltf.named_meshes["Cylinder".into()])
-        .unwrap();
+    let hex_visual = assets_gltfmesh.get(&gltf.named_meshes["Cylinder"]).unwrap();
 
     // FIXME remove assert
     assert_eq!(hex_visual.primitives.len(), 1);
 
     (
         hex_visual.primitives[0].mesh.clone(),
-        match selected {
-            true => gltf.named_materials["Selected".into()].clone(),
-            false => gltf.named_materials[tile_kind.material_name().into()].clone(),
+        match (selected, tile.fire) {
+            (true, _) => gltf.named_materials["Selected"].clone(),
+            (false, _) if tile.lava.is_molten() => tile_materials.lava.clone(),
+            (false, _) if tile.snow => tile_materials.snow.clone(),
+            (false, Fire::Burning { .. }) => tile_materials.burning.clone(),
+            (false, Fire::Ash { .. }) => tile_materials.ash.clone(),
+            (false, Fire::None) => vegetation_tinted(
+                &gltf.named_materials[tile.kind.material_name()],
+                tile.vegetation,
+                materials,
+                tile_materials,
+            ),
         },
     )
 }
 
+fn vegetation_tinted(
+    base: &Handle<StandardMaterial>,
+    vegetation: Vegetation,
+    materials: &mut Assets<StandardMaterial>,
+    tile_materials: &mut TileMaterials,
+) -> Handle<StandardMaterial> {
+    let step = (vegetation.density * VEGETATION_TINT_STEPS).round() as u8;
+    if step == 0 || vegetation.plant == PlantKind::None {
+        return base.clone();
+    }
+
+    tile_materials
+        .tinted
+        .entry((base.clone(), vegetation.plant, step))
+        .or_insert_with(|| {
+            let plant_color = match vegetation.plant {
+                PlantKind::None => unreachable!(),
+                PlantKind::Grass => Color::rgb(0.55, 0.8, 0.3),
+                PlantKind::Shrub => Color::rgb(0.4, 0.55, 0.2),
+                PlantKind::Forest => Color::rgb(0.1, 0.4, 0.15),
+            };
+            // unwrap safety: `base` came from the loaded glTF
+            let mut material = materials.get(base).unwrap().clone();
+            let amount = step as f32 / VEGETATION_TINT_STEPS;
+            let base_color = Vec4::from(material.base_color);
+            material.base_color = base_color.lerp(Vec4::from(plant_color), amount).into();
+            materials.add(material)
+        })
+        .clone()
+}
+
+/// Moves render tiles around to cover the screen and rebuilds the visuals of the ones that moved,
+/// whose tile (or a neighbour's) changed, or that got hovered/unhovered
+#[allow(clippy::too_many_arguments)]
 fn update_render_entities(
     mut cmds: Commands<'_, '_>,
-    mut render_entities: Query<(Entity, &mut RenderTileEntity), Without<Camera>>,
+    mut render_entities: Query<
+        (
+            Entity,
+            &mut RenderTileEntity,
+            ChangeTrackers<RenderTileEntity>,
+        ),
+        Without<Camera>,
+    >,
     window_size: Res<WindowSize>,
     mut camera: Query<(&Transform, &Frustum, &mut RayCastSource<MyRaycastSet>), With<Camera>>,
-    map: CurrentHexMap<'_, '_>,
+    (map, selected, mut tile_changes): (
+        CurrentHexMap<'_, '_>,
+        Res<SelectedSurface>,
+        EventReader<'_, '_, SurfaceTileChanged>,
+    ),
     window: Res<Windows>,
     (hex_object_asset, assets_gltf, assets_gltfmesh): (
         Res<HexObjectAsset>,
         Res<Assets<Gltf>>,
         Res<Assets<GltfMesh>>,
     ),
+    (mut materials, mut tile_materials): (ResMut<Assets<StandardMaterial>>, ResMut<TileMaterials>),
+    mut hovered_hex: ResMut<HoveredHex>,
 ) {
+    let Some(map) = map.hexmap() else {
+        return;
+    };
     let plane_center = {
         let (camera_pos, camera_frustum, _) = camera.single();
-        let ray_dir = camera_frustum.planes[4].normal();
-        ray_intersects_xy_plane(0.0, camera_pos.translation, ray_dir.into()).unwrap()
+        camera_focus(camera_pos, camera_frustum)
     };
 
     let start_x = plane_center.x - window_size.0 / 2. - HEX_WIDTH * 2.;
@@ -233,11 +374,24 @@ fn update_render_entities(
         }
         current_y += 1;
     }
+    // render tiles already showing a hex that's still on screen stay put, the rest get moved
+    // to whichever hexes are newly on screen
+    let mut spare = vec![];
+    for (entity, render_tile, _) in render_entities.iter() {
+        let pos = HexPos {
+            q: render_tile.q,
+            r: render_tile.r,
+        };
+        if !tiles.remove(&pos) {
+            spare.push(entity);
+        }
+    }
     let mut tile_iter = tiles.into_iter();
-    let mut query_iter = render_entities.iter_mut();
+    let mut spare_iter = spare.into_iter();
     loop {
-        match (query_iter.next(), tile_iter.next()) {
-            (Some((_, mut tile_pos)), Some(tile)) => {
+        match (spare_iter.next(), tile_iter.next()) {
+            (Some(entity), Some(tile)) => {
+                let (_, mut tile_pos, _) = render_entities.get_mut(entity).unwrap();
                 tile_pos.q = tile.q;
                 tile_pos.r = tile.r;
             }
@@ -251,12 +405,12 @@ fn update_render_entities(
                 ));
                 // TODO: separate this out into a system that creates and manages a pool of hex meshes, and this system which moves and updates them as needed
             }
-            (Some((entity, _)), None) => cmds.entity(entity).despawn(),
+            (Some(entity), None) => cmds.entity(entity).despawn(),
             (None, None) => break,
         }
     }
 
-    let map = map.hexmap();
+    let (width, height) = (map.width() as u32, map.height() as u32);
 
     let (_, _, mut raycast_source) = camera.single_mut();
     let window = window.get_primary().unwrap();
@@ -265,38 +419,58 @@ fn update_render_entities(
         raycast_source.cast_method = RayCastMethod::Screenspace(cursor_pos);
     }
     let selected_hex = raycast_source.intersect_top().map(|(entity, _)| {
-        let (_, tile) = render_entities.get(entity).unwrap();
+        let (_, tile, _) = render_entities.get(entity).unwrap();
         crate::hexmap::wrap_hex_pos(
             HexPos {
                 q: tile.q,
                 r: tile.r,
             },
-            16,
-            16,
+            width,
+            height,
         )
     });
 
-    for (entity, render_tile) in render_entities.iter_mut() {
+    // a tile's column goes down to its lowest neighbour so neighbours of changed tiles need redoing too
+    let mut changed = HashSet::with_hasher(FixedState);
+    for SurfaceTileChanged { surface, change } in tile_changes.iter() {
+        if *surface == selected.0 {
+            changed.insert(change.pos);
+            changed.extend(map.wrapped_neighbors(change.pos));
+        }
+    }
+    if hovered_hex.0 != selected_hex {
+        changed.extend(hovered_hex.0);
+        changed.extend(selected_hex);
+        hovered_hex.0 = selected_hex;
+    }
+    let rebuild_all = selected.is_changed();
+
+    for (entity, render_tile, render_tile_tracker) in render_entities.iter_mut() {
         let tile_pos = HexPos {
             q: render_tile.q,
             r: render_tile.r,
         };
-        let wrapped_tile_pos = crate::hexmap::wrap_hex_pos(tile_pos, 16, 16);
+        let wrapped_tile_pos = crate::hexmap::wrap_hex_pos(tile_pos, width, height);
+        if !rebuild_all && !render_tile_tracker.is_changed() && !changed.contains(&wrapped_tile_pos)
+        {
+            continue;
+        }
         let tile = map.get(wrapped_tile_pos);
 
-        let lowest_neighbor_height = wrapped_tile_pos
-            .neighbors()
-            .map(|pos| crate::hexmap::wrap_hex_pos(pos, 16, 16))
+        let lowest_neighbor_height = map
+            .wrapped_neighbors(wrapped_tile_pos)
             .map(|pos| map.get(pos).height)
             .min()
             .unwrap();
 
         let (mesh, material) = create_hex_visual(
             selected_hex == Some(wrapped_tile_pos),
-            tile.kind,
+            tile,
             &hex_object_asset,
             &assets_gltf,
             &assets_gltfmesh,
+            &mut materials,
+            &mut tile_materials,
         );
 
         cmds.entity(entity)
@@ -313,12 +487,8 @@ fn update_render_entities(
         for depth in lowest_neighbor_height..tile.height {
             cmds.entity(entity).with_children(|child| {
                 child.spawn_bundle(PbrBundle {
-                    transform: Transform::from_translation(vec3(
-                        0.0,
-                        0.0,
-                        -1. * (depth + 1) as f32,
-                    ))
-                    .with_scale(Vec2::ONE.extend(1.1)),
+                    transform: Transform::from_translation(vec3(0.0, 0.0, -((depth + 1) as f32)))
+                        .with_scale(Vec2::ONE.extend(1.1)),
                     mesh: mesh.clone(),
                     material: material.clone(),
                     ..default()
@@ -328,13 +498,142 @@ fn update_render_entities(
     }
 }
 
+/// Keeps a `SurfaceMirror` for every agent and settlement in the selected surface, drawn on top of its tile
+fn mirror_surface_entities(
+    mut cmds: Commands<'_, '_>,
+    mut mirrors: Query<(Entity, &SurfaceMirror, &mut Transform), Without<Camera>>,
+    camera: Query<(&Transform, &Frustum), With<Camera>>,
+    mut surfaces: ResMut<Surfaces>,
+    selected: Res<SelectedSurface>,
+    visuals: Res<MirrorVisuals>,
+) {
+    let Some(world) = surfaces.get_mut(selected.0) else {
+        return;
+    };
+    let mut settlements = world.query::<(Entity, &HexPos, &Settlement)>();
+    let map = world.resource::<HexMap<MyTileData>>();
+    let focus = {
+        let (camera_pos, camera_frustum) = camera.single();
+        camera_focus(camera_pos, camera_frustum)
+    };
+
+    // the map repeats in both directions so draw everything on whichever copy of its
+    // tile is closest to where the camera is looking
+    let on_tile = |pos: HexPos| {
+        let period_q = hex_pos_to_pos(HexPos {
+            q: map.width() as i32,
+            r: 0,
+        });
+        let period_r = hex_pos_to_pos(HexPos {
+            q: 0,
+            r: map.height() as i32,
+        });
+        let base = hex_pos_to_pos(pos);
+        let closest = [-1.0, 0.0, 1.0]
+            .into_iter()
+            .flat_map(|q| [-1.0, 0.0, 1.0].map(|r| base + period_q * q + period_r * r))
+            .min_by(|a, b| a.distance(focus).total_cmp(&b.distance(focus)))
+            .unwrap();
+        Transform::from_translation(
+            closest.extend(HEX_TALLNESS * (map.get(pos).height as f32 + 1.0)),
+        )
+    };
+
+    let mut wanted = world
+        .resource::<AgentIndex>()
+        .iter()
+        .map(|(pos, agent)| (agent, (on_tile(pos), MirrorKind::Agent)))
+        .collect::<HashMap<_, _>>();
+    for (entity, &pos, settlement) in settlements.iter(world) {
+        let size = 8.0 + settlement.population.sqrt();
+        let transform = on_tile(pos).with_scale(Vec3::new(size, size, size / 2.0));
+        wanted.insert(entity, (transform, MirrorKind::Settlement));
+    }
+
+    for (entity, mirror, mut transform) in mirrors.iter_mut() {
+        match wanted.remove(&mirror.entity) {
+            Some((wanted, _)) if mirror.surface == selected.0 => *transform = wanted,
+            _ => cmds.entity(entity).despawn(),
+        }
+    }
+    for (entity, (transform, kind)) in wanted {
+        let (mesh, material) = match kind {
+            MirrorKind::Agent => (&visuals.agent_mesh, &visuals.agent_material),
+            MirrorKind::Settlement => (&visuals.settlement_mesh, &visuals.settlement_material),
+        };
+        cmds.spawn_bundle(PbrBundle {
+            transform,
+            mesh: mesh.clone(),
+            material: material.clone(),
+            ..default()
+        })
+        .insert(SurfaceMirror {
+            surface: selected.0,
+            entity,
+        });
+    }
+}
+
+/// `summer_factor` is from `Calendar::summer_factor`
+fn sun_rotation(summer_factor: f32) -> Quat {
+    Quat::from_euler(
+        EulerRot::ZYX,
+        0.0,
+        2.0_f32 * std::f32::consts::TAU / 10.0 - SUN_SEASONAL_SWING * summer_factor,
+        -std::f32::consts::FRAC_PI_4,
+    )
+}
+
+/// Moves the sun with the seasons of the selected surface
+fn update_sun(
+    mut lights: Query<&mut Transform, With<DirectionalLight>>,
+    surfaces: Res<Surfaces>,
+    selected: Res<SelectedSurface>,
+) {
+    let Some(world) = surfaces.get(selected.0) else {
+        return;
+    };
+    let tick = world.resource::<SurfaceTick>().0;
+    let rotation = sun_rotation(world.resource::<Calendar>().summer_factor(tick));
+    for mut transform in lights.iter_mut() {
+        if transform.rotation != rotation {
+            transform.rotation = rotation;
+        }
+    }
+}
+
+/// Tiles lower than the sea level end up under the plane, see `SeaLevel::is_below`
+fn update_water_plane(
+    mut plane: Query<&mut Transform, (With<WaterPlane>, Without<Camera>)>,
+    camera: Query<(&Transform, &Frustum), With<Camera>>,
+    surfaces: Res<Surfaces>,
+    selected: Res<SelectedSurface>,
+) {
+    let focus = {
+        let (camera_pos, camera_frustum) = camera.single();
+        camera_focus(camera_pos, camera_frustum)
+    };
+    let Some(world) = surfaces.get(selected.0) else {
+        return;
+    };
+    let level = world.resource::<SeaLevel>().current;
+    let z = HEX_TALLNESS * level + HEX_MESH_TOP * HEX_SCALAR;
+    let wanted = Transform::from_translation(focus.extend(z));
+    let mut transform = plane.single_mut();
+    if *transform != wanted {
+        *transform = wanted;
+    }
+}
+
 fn update_camera_pos(
     mut cam: Query<(&mut Transform, &ActionState<Action>), With<Camera>>,
     map: CurrentHexMap<'_, '_>,
     mut window_size: ResMut<WindowSize>,
     windows: Res<Windows>,
 ) {
-    let map = map.hexmap();
+    let Some(map) = map.hexmap() else {
+        return;
+    };
     const CAM_SPEED: f32 = 4.0;
 
     let (mut pos, actions) = cam.single_mut();
@@ -375,6 +674,12 @@ fn hex_pos_to_pos(pos: HexPos) -> Vec2 {
     vec2(x, y)
 }
 
+/// Where the middle of the screen hits the ground plane
+fn camera_focus(camera_pos: &Transform, camera_frustum: &Frustum) -> Vec2 {
+    let ray_dir = camera_frustum.planes[4].normal();
+    ray_intersects_xy_plane(0.0, camera_pos.translation, ray_dir.into()).unwrap()
+}
+
 fn ray_intersects_xy_plane(plane_z: f32, ray_pos: Vec3, ray_dir: Vec3) -> Option<Vec2> {
     if (ray_pos.z < plane_z && ray_dir.z < 0.0) || (ray_pos.z > plane_z && ray_dir.z > 0.0) {
         return None;
@@ -0,0 +1,597 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    ops::{Index, IndexMut},
+};
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{bevy_egui::EguiContext, egui, Inspectable};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    flowfield::{tile_cost, FlowFields, GoalSet},
+    hexmap::{HexMap, HexPos},
+    rng::SurfaceRng,
+    schedule::{SurfaceStage, SurfaceSystem},
+    settlements::{Settlement, TerritoryClaims},
+    simulation::{MyTileData, TileKind},
+    snapshot,
+    surfaces::{SelectedSurface, SurfaceTick, Surfaces},
+    vegetation::PlantKind,
+    AppState,
+};
+
+/// Chance of a land tile starting out with a stone or ore deposit
+const STONE_CHANCE: f32 = 0.3;
+const ORE_CHANCE: f32 = 0.15;
+/// Ore only turns up this high or higher
+const ORE_MIN_HEIGHT: u8 = 3;
+const STONE_AMOUNT: f32 = 500.0;
+const ORE_AMOUNT: f32 = 200.0;
+/// Fish stocks regrow towards this, a fished out tile recovers on its own
+const FISH_STOCK: f32 = 50.0;
+const FISH_REGROWTH: f32 = 0.05;
+/// Timber per point of forest density, vegetation lost per unit of timber cut
+const TIMBER_PER_DENSITY: f32 = 100.0;
+const DENSITY_PER_TIMBER: f32 = 0.01;
+/// One producer per this many people
+const PEOPLE_PER_PRODUCER: f32 = 50.0;
+/// Producers send their goods off once they've built up this much
+const SHIPMENT_SIZE: f32 = 5.0;
+/// Goods used up per person per tick
+const CONSUMPTION_PER_PERSON: Goods = Goods([0.0005, 0.0002, 0.0005, 0.001]);
+/// Settlements with more than this many ticks of consumption in stock trade the rest away
+const TRADE_SURPLUS_TICKS: f32 = 500.0;
+/// How many ticks of accounts each surface keeps
+const LEDGER_LEN: usize = 1000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Inspectable, Serialize, Deserialize)]
+pub enum ResourceKind {
+    Stone,
+    Ore,
+    Timber,
+    Fish,
+}
+
+impl ResourceKind {
+    pub const ALL: [Self; 4] = [Self::Stone, Self::Ore, Self::Timber, Self::Fish];
+
+    /// How much one producer can extract per tick from a deposit of this kind on `tile`,
+    /// `0.0` if the terrain doesn't support it at all
+    pub fn yield_per_tick(self, tile: &MyTileData) -> f32 {
+        match (self, tile.kind) {
+            (Self::Stone, TileKind::Rock) => 0.5 + 0.1 * tile.height as f32,
+            (Self::Ore, TileKind::Rock) if tile.height >= ORE_MIN_HEIGHT => {
+                0.1 * (tile.height - ORE_MIN_HEIGHT + 1) as f32
+            }
+            (Self::Timber, TileKind::Rock) => tile.vegetation.density,
+            (Self::Fish, TileKind::Water) => 0.5,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Something worth extracting on a tile, `amount` runs out as producers work it
+#[derive(Copy, Clone, Debug, PartialEq, Inspectable, Serialize, Deserialize)]
+pub struct Deposit {
+    pub kind: ResourceKind,
+    pub amount: f32,
+}
+
+/// An amount of every `ResourceKind`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Goods(pub [f32; 4]);
+
+impl Index<ResourceKind> for Goods {
+    type Output = f32;
+
+    fn index(&self, kind: ResourceKind) -> &f32 {
+        &self.0[kind as usize]
+    }
+}
+
+impl IndexMut<ResourceKind> for Goods {
+    fn index_mut(&mut self, kind: ResourceKind) -> &mut f32 {
+        &mut self.0[kind as usize]
+    }
+}
+
+impl std::ops::AddAssign for Goods {
+    fn add_assign(&mut self, other: Self) {
+        for kind in ResourceKind::ALL {
+            self[kind] += other[kind];
+        }
+    }
+}
+
+/// Goods held by a settlement
+#[derive(Component, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Stockpile(pub Goods);
+
+/// Works the deposit on its hex for the settlement `deliver_to`, always paired with a `HexPos`
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Producer {
+    #[serde(with = "snapshot::entity_bits")]
+    pub deliver_to: Entity,
+    /// Extracted but not shipped yet
+    pub output: f32,
+}
+
+/// Goods on their way to a settlement's stockpile, moving one hex per tick along its flow field
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Shipment {
+    pub kind: ResourceKind,
+    pub amount: f32,
+    #[serde(with = "snapshot::entity_bits")]
+    pub to: Entity,
+}
+
+/// Everything that happened to goods on a surface during one tick
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TickAccount {
+    pub tick: u64,
+    pub produced: Goods,
+    pub consumed: Goods,
+    /// What settlements wanted to consume but didn't have
+    pub shortfall: Goods,
+    pub shipped: Goods,
+    pub delivered: Goods,
+    /// Shipments that couldn't reach their settlement, or whose settlement is gone
+    pub lost: Goods,
+    /// Totals at the end of the tick
+    pub stockpiled: Goods,
+    pub in_transit: Goods,
+    /// Sum of `tile_cost` of every hex shipments stepped onto
+    pub transport_cost: u64,
+}
+
+/// Per tick accounts for a surface, the last `LEDGER_LEN` ticks are kept
+// Not Inspectable because of VecDeque
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EconomyLedger {
+    /// Filled in by the economy systems as the tick runs
+    current: TickAccount,
+    history: VecDeque<TickAccount>,
+}
+
+impl EconomyLedger {
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = &TickAccount> {
+        self.history.iter()
+    }
+
+    pub fn last(&self) -> Option<&TickAccount> {
+        self.history.back()
+    }
+
+    /// One row per tick, one column per kind of goods for every category
+    pub fn to_csv(&self) -> String {
+        const CATEGORIES: [&str; 8] = [
+            "produced",
+            "consumed",
+            "shortfall",
+            "shipped",
+            "delivered",
+            "lost",
+            "stockpiled",
+            "in_transit",
+        ];
+        let mut csv = String::from("tick");
+        for category in CATEGORIES {
+            for kind in ResourceKind::ALL {
+                write!(csv, ",{category}_{kind:?}").unwrap();
+            }
+        }
+        csv.push_str(",transport_cost\n");
+
+        for account in self.history.iter() {
+            write!(csv, "{}", account.tick).unwrap();
+            for goods in [
+                account.produced,
+                account.consumed,
+                account.shortfall,
+                account.shipped,
+                account.delivered,
+                account.lost,
+                account.stockpiled,
+                account.in_transit,
+            ] {
+                for kind in ResourceKind::ALL {
+                    write!(csv, ",{}", goods[kind]).unwrap();
+                }
+            }
+            writeln!(csv, ",{}", account.transport_cost).unwrap();
+        }
+        csv
+    }
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+enum EconomySystem {
+    UpdateDeposits,
+    AssignProducers,
+    Extract,
+    Trade,
+    Ship,
+}
+
+pub fn init_app(app: &mut App) {
+    app.add_system(economy_window.run_in_state(AppState::Playing));
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| {
+            world.init_resource::<EconomyLedger>();
+            world.resource_scope(|world, mut rng: Mut<SurfaceRng>| {
+                let rng = rng.stream("economy");
+                let mut map = world.resource_mut::<HexMap<MyTileData>>();
+                for pos in map.positions() {
+                    let tile = map.get_mut(pos);
+                    if tile.deposit.is_none() {
+                        tile.deposit = initial_deposit(tile, rng.next_f32());
+                    }
+                }
+            });
+        })
+        .push_state_resource::<EconomyLedger>()
+        .push_state_component::<Stockpile>()
+        .push_state_component::<Producer>()
+        .push_state_component::<Shipment>()
+        .push_system(SurfaceSystem::new(update_deposits).label(EconomySystem::UpdateDeposits))
+        .push_system(SurfaceSystem::new(assign_producers).label(EconomySystem::AssignProducers))
+        .push_system(
+            SurfaceSystem::new(extract_resources)
+                .label(EconomySystem::Extract)
+                .after(EconomySystem::UpdateDeposits)
+                .after(EconomySystem::AssignProducers),
+        )
+        .push_system(
+            SurfaceSystem::new(trade_surplus)
+                .label(EconomySystem::Trade)
+                .after(EconomySystem::Extract),
+        )
+        .push_system(
+            SurfaceSystem::new(move_shipments)
+                .label(EconomySystem::Ship)
+                .after(EconomySystem::Trade),
+        )
+        .push_system(SurfaceSystem::new(consume_goods).after(EconomySystem::Ship))
+        .push_system(SurfaceSystem::new(close_accounts).in_stage(SurfaceStage::PostSim));
+}
+
+/// `roll` is a random number in `0.0..1.0`
+fn initial_deposit(tile: &MyTileData, roll: f32) -> Option<Deposit> {
+    let (kind, amount) = match tile.kind {
+        TileKind::Water => (ResourceKind::Fish, FISH_STOCK),
+        TileKind::Rock if tile.height >= ORE_MIN_HEIGHT && roll < ORE_CHANCE => {
+            (ResourceKind::Ore, ORE_AMOUNT)
+        }
+        TileKind::Rock if roll < ORE_CHANCE + STONE_CHANCE => (ResourceKind::Stone, STONE_AMOUNT),
+        TileKind::Rock => return None,
+    };
+    Some(Deposit { kind, amount })
+}
+
+/// Fish regrow, forests become timber deposits and stop being ones once they're cut down or burnt
+fn update_deposits(mut map: ResMut<HexMap<MyTileData>>) {
+    for pos in map.positions() {
+        let tile = map.get(pos);
+        let forested = tile.vegetation.plant == PlantKind::Forest;
+        let deposit = match tile.deposit {
+            Some(Deposit {
+                kind: ResourceKind::Fish,
+                amount,
+            }) if tile.kind == TileKind::Water => Some(Deposit {
+                kind: ResourceKind::Fish,
+                amount: (amount + FISH_REGROWTH).min(FISH_STOCK),
+            }),
+            // drained or dried up
+            Some(Deposit {
+                kind: ResourceKind::Fish,
+                ..
+            }) => None,
+            Some(Deposit {
+                kind: ResourceKind::Timber,
+                ..
+            })
+            | None
+                if forested =>
+            {
+                Some(Deposit {
+                    kind: ResourceKind::Timber,
+                    amount: tile.vegetation.density * TIMBER_PER_DENSITY,
+                })
+            }
+            Some(Deposit {
+                kind: ResourceKind::Timber,
+                ..
+            }) => None,
+            None if tile.kind == TileKind::Water => Some(Deposit {
+                kind: ResourceKind::Fish,
+                amount: 0.0,
+            }),
+            deposit => deposit,
+        };
+        if tile.deposit != deposit {
+            map.get_mut(pos).deposit = deposit;
+        }
+    }
+}
+
+/// Settlements put people to work on the deposits in their territory, one producer per
+/// `PEOPLE_PER_PRODUCER` people, best yielding deposits first
+fn assign_producers(
+    mut cmds: Commands<'_, '_>,
+    map: Res<HexMap<MyTileData>>,
+    claims: Res<TerritoryClaims>,
+    settlements: Query<(Entity, &Settlement)>,
+    producers: Query<(Entity, &HexPos, &Producer)>,
+) {
+    let mut worked = HexMap::new(
+        map.width(),
+        map.height(),
+        vec![None::<Entity>; map.width() * map.height()],
+    );
+    for (entity, &pos, producer) in producers.iter() {
+        let workable = map
+            .get(pos)
+            .deposit
+            .is_some_and(|deposit| deposit.amount > 0.0);
+        if *claims.0.get(pos) == Some(producer.deliver_to) && workable {
+            *worked.get_mut_untracked(pos) = Some(producer.deliver_to);
+        } else {
+            cmds.entity(entity).despawn();
+        }
+    }
+
+    for (settlement, stats) in settlements.iter() {
+        let wanted = (stats.population / PEOPLE_PER_PRODUCER) as usize;
+        let working = map
+            .positions()
+            .filter(|&pos| *worked.get(pos) == Some(settlement))
+            .count();
+        if working >= wanted {
+            continue;
+        }
+
+        let mut candidates = map
+            .positions()
+            .filter(|&pos| *claims.0.get(pos) == Some(settlement) && worked.get(pos).is_none())
+            .filter_map(|pos| {
+                let tile = map.get(pos);
+                let deposit = tile.deposit.filter(|deposit| deposit.amount > 0.0)?;
+                Some((pos, deposit.kind.yield_per_tick(tile)))
+            })
+            .filter(|&(_, rate)| rate > 0.0)
+            .collect::<Vec<_>>();
+        // stable sort so equal yields keep row-major order and the result stays deterministic
+        candidates.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        for (pos, _) in candidates.into_iter().take(wanted - working) {
+            *worked.get_mut_untracked(pos) = Some(settlement);
+            cmds.spawn().insert_bundle((
+                pos,
+                Producer {
+                    deliver_to: settlement,
+                    output: 0.0,
+                },
+            ));
+        }
+    }
+}
+
+fn extract_resources(
+    mut cmds: Commands<'_, '_>,
+    mut map: ResMut<HexMap<MyTileData>>,
+    mut ledger: ResMut<EconomyLedger>,
+    mut producers: Query<(&HexPos, &mut Producer)>,
+) {
+    for (&pos, mut producer) in producers.iter_mut() {
+        let tile = map.get_mut(pos);
+        let Some(mut deposit) = tile.deposit else {
+            continue;
+        };
+        let kind = deposit.kind;
+        let amount = kind.yield_per_tick(tile).min(deposit.amount);
+        deposit.amount -= amount;
+        if kind == ResourceKind::Timber {
+            tile.vegetation.density =
+                (tile.vegetation.density - amount * DENSITY_PER_TIMBER).max(0.0);
+        }
+        // fished out waters regrow, everything else is gone for good
+        tile.deposit = match deposit.amount <= 0.0 && kind != ResourceKind::Fish {
+            true => None,
+            false => Some(deposit),
+        };
+        ledger.current.produced[kind] += amount;
+
+        producer.output += amount;
+        if producer.output >= SHIPMENT_SIZE {
+            ledger.current.shipped[kind] += producer.output;
+            cmds.spawn().insert_bundle((
+                pos,
+                Shipment {
+                    kind,
+                    amount: std::mem::take(&mut producer.output),
+                    to: producer.deliver_to,
+                },
+            ));
+        }
+    }
+}
+
+/// Settlements sitting on more than they need send some of the extra, up to a shipment's worth,
+/// to whoever has the least
+fn trade_surplus(
+    mut cmds: Commands<'_, '_>,
+    mut ledger: ResMut<EconomyLedger>,
+    mut settlements: Query<(Entity, &HexPos, &Settlement, &mut Stockpile)>,
+) {
+    for kind in ResourceKind::ALL {
+        let ticks_of_stock = |settlement: &Settlement, stockpile: &Stockpile| {
+            stockpile.0[kind] / (settlement.population * CONSUMPTION_PER_PERSON[kind])
+        };
+        let richest = settlements
+            .iter()
+            .map(|(entity, _, settlement, stockpile)| {
+                (entity, ticks_of_stock(settlement, stockpile))
+            })
+            .filter(|&(_, ticks)| ticks > TRADE_SURPLUS_TICKS)
+            .min_by(|(_, a), (_, b)| b.total_cmp(a));
+        let poorest = settlements
+            .iter()
+            .map(|(entity, _, settlement, stockpile)| {
+                (entity, ticks_of_stock(settlement, stockpile))
+            })
+            .filter(|&(_, ticks)| ticks < TRADE_SURPLUS_TICKS / 4.0)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        let (Some((from, _)), Some((to, _))) = (richest, poorest) else {
+            continue;
+        };
+
+        let (_, &pos, settlement, mut stockpile) = settlements.get_mut(from).unwrap();
+        let needed = TRADE_SURPLUS_TICKS * settlement.population * CONSUMPTION_PER_PERSON[kind];
+        let amount = (stockpile.0[kind] - needed).min(SHIPMENT_SIZE);
+        if amount <= 0.0 {
+            continue;
+        }
+        stockpile.0[kind] -= amount;
+        ledger.current.shipped[kind] += amount;
+        cmds.spawn()
+            .insert_bundle((pos, Shipment { kind, amount, to }));
+    }
+}
+
+fn move_shipments(
+    mut cmds: Commands<'_, '_>,
+    map: Res<HexMap<MyTileData>>,
+    mut fields: ResMut<FlowFields>,
+    mut ledger: ResMut<EconomyLedger>,
+    mut shipments: Query<(Entity, &mut HexPos, &Shipment)>,
+    mut stockpiles: Query<(&HexPos, &mut Stockpile), Without<Shipment>>,
+) {
+    for (entity, mut pos, shipment) in shipments.iter_mut() {
+        let Ok((&goal, mut stockpile)) = stockpiles.get_mut(shipment.to) else {
+            ledger.current.lost[shipment.kind] += shipment.amount;
+            cmds.entity(entity).despawn();
+            continue;
+        };
+        if *pos == goal {
+            stockpile.0[shipment.kind] += shipment.amount;
+            ledger.current.delivered[shipment.kind] += shipment.amount;
+            cmds.entity(entity).despawn();
+            continue;
+        }
+
+        let field = fields.get_or_insert(&GoalSet::new([goal]), &map);
+        // goods from the water (i.e. fish) get landed on the cheapest shore next to them first
+        let next = field.next_step(*pos).or_else(|| {
+            map.wrapped_neighbors(*pos)
+                .filter(|&neighbor| field.cost_to_goal(neighbor).is_some())
+                .min_by_key(|&neighbor| field.cost_to_goal(neighbor))
+        });
+        match next {
+            Some(next) => {
+                ledger.current.transport_cost += tile_cost(map.get(next)).unwrap_or(0) as u64;
+                *pos = next;
+            }
+            None => {
+                ledger.current.lost[shipment.kind] += shipment.amount;
+                cmds.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+fn consume_goods(
+    mut cmds: Commands<'_, '_>,
+    mut ledger: ResMut<EconomyLedger>,
+    mut settlements: Query<(Entity, &Settlement, Option<&mut Stockpile>)>,
+) {
+    for (entity, settlement, stockpile) in settlements.iter_mut() {
+        let Some(mut stockpile) = stockpile else {
+            cmds.entity(entity).insert(Stockpile::default());
+            continue;
+        };
+        for kind in ResourceKind::ALL {
+            let wanted = settlement.population * CONSUMPTION_PER_PERSON[kind];
+            let eaten = wanted.min(stockpile.0[kind]);
+            stockpile.0[kind] -= eaten;
+            ledger.current.consumed[kind] += eaten;
+            ledger.current.shortfall[kind] += wanted - eaten;
+        }
+    }
+}
+
+/// Totals up what's stockpiled and in transit and files the tick's account away
+fn close_accounts(
+    tick: Res<SurfaceTick>,
+    mut ledger: ResMut<EconomyLedger>,
+    stockpiles: Query<&Stockpile>,
+    shipments: Query<&Shipment>,
+) {
+    let mut account = std::mem::take(&mut ledger.current);
+    account.tick = tick.0;
+    for stockpile in stockpiles.iter() {
+        account.stockpiled += stockpile.0;
+    }
+    for shipment in shipments.iter() {
+        account.in_transit[shipment.kind] += shipment.amount;
+    }
+
+    if ledger.history.len() == LEDGER_LEN {
+        ledger.history.pop_front();
+    }
+    ledger.history.push_back(account);
+}
+
+/// Last tick's accounts for the selected surface, with a button to dump the whole ledger as CSV
+fn economy_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    surfaces: Res<Surfaces>,
+    selected: Res<SelectedSurface>,
+) {
+    let Some(world) = surfaces.get(selected.0) else {
+        return;
+    };
+    let ledger = world.resource::<EconomyLedger>();
+
+    egui::Window::new("Economy").show(egui_ctx.ctx_mut(), |ui| {
+        let Some(account) = ledger.last() else {
+            ui.label("nothing has happened yet");
+            return;
+        };
+        ui.label(format!("tick {}", account.tick));
+        egui::Grid::new("economy_accounts").show(ui, |ui| {
+            ui.label("");
+            for kind in ResourceKind::ALL {
+                ui.label(format!("{kind:?}"));
+            }
+            ui.end_row();
+            for (name, goods) in [
+                ("produced", account.produced),
+                ("consumed", account.consumed),
+                ("shortfall", account.shortfall),
+                ("delivered", account.delivered),
+                ("lost", account.lost),
+                ("stockpiled", account.stockpiled),
+                ("in transit", account.in_transit),
+            ] {
+                ui.label(name);
+                for kind in ResourceKind::ALL {
+                    ui.monospace(format!("{:.2}", goods[kind]));
+                }
+                ui.end_row();
+            }
+        });
+        ui.label(format!("transport cost {}", account.transport_cost));
+
+        if ui.button("Export CSV").clicked() {
+            let path = format!("economy_surface_{}.csv", selected.0);
+            match std::fs::write(&path, ledger.to_csv()) {
+                Ok(()) => info!("wrote economy ledger to {path}"),
+                Err(err) => error!("couldn't write economy ledger to {path}: {err}"),
+            }
+        }
+    });
+}
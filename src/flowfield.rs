@@ -0,0 +1,430 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agents::{can_step, Destination},
+    hexmap::{HexMap, HexPos},
+    schedule::{SurfaceStage, SurfaceSystem},
+    simulation::{MyTileData, TileKind},
+    surfaces::Surfaces,
+};
+
+const UNREACHABLE: u32 = u32::MAX;
+/// Fields nothing has asked for in this many ticks get dropped
+const FIELD_IDLE_TICKS: u32 = 200;
+
+/// Cost of stepping onto `tile`, `None` if it can't be walked on at all
+pub fn tile_cost(tile: &MyTileData) -> Option<u32> {
+    match tile.kind {
+        TileKind::Water => None,
+        _ if tile.lava.is_molten() => None,
+        TileKind::Rock => Some(10 + 5 * tile.height as u32),
+    }
+}
+
+/// Cost of stepping from `from` onto `to`, `None` if agents can't make that step
+pub fn step_cost(from: &MyTileData, to: &MyTileData) -> Option<u32> {
+    tile_cost(to).filter(|_| can_step(from, to))
+}
+
+/// Everything about a tile `step_cost` looks at, for spotting tiles that changed
+type StepKey = (TileKind, bool, bool, u8);
+
+fn step_key(tile: &MyTileData) -> StepKey {
+    (
+        tile.kind,
+        tile.fire.is_burning(),
+        tile.lava.is_molten(),
+        tile.height,
+    )
+}
+
+/// Sorted and deduplicated so the same goals always hash the same regardless of order
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GoalSet(Vec<HexPos>);
+
+impl GoalSet {
+    pub fn new(goals: impl IntoIterator<Item = HexPos>) -> Self {
+        let mut goals = goals.into_iter().collect::<Vec<_>>();
+        goals.sort_by_key(|pos| (pos.r, pos.q));
+        goals.dedup();
+        Self(goals)
+    }
+
+    pub fn goals(&self) -> &[HexPos] {
+        &self.0
+    }
+}
+
+/// Cheapest cost to reach the nearest goal from every hex, plus which way to step to get there.
+/// Everything wraps around the map edges.
+// Not Inspectable because HexMap isn't
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowField {
+    goals: GoalSet,
+    /// `step_key` of every tile as of the last update, used to spot tiles that changed
+    keys: HexMap<StepKey>,
+    integration: HexMap<u32>,
+    /// Index into `HexPos::neighbors` of the best step from each hex
+    directions: HexMap<Option<u8>>,
+    /// Whether `FlowFields::get_or_insert` handed the field out since the last update
+    used: bool,
+    /// Updates in a row nothing used the field for
+    idle_ticks: u32,
+}
+
+impl FlowField {
+    pub fn new(goals: GoalSet, map: &HexMap<MyTileData>) -> Self {
+        let (width, height) = (map.width(), map.height());
+        let mut field = Self {
+            keys: HexMap::new(
+                width,
+                height,
+                map.positions().map(|pos| step_key(map.get(pos))),
+            ),
+            integration: HexMap::new(width, height, vec![UNREACHABLE; width * height]),
+            directions: HexMap::new(width, height, vec![None; width * height]),
+            goals,
+            used: true,
+            idle_ticks: 0,
+        };
+
+        let mut frontier = BinaryHeap::new();
+        for &goal in field.goals.0.iter() {
+            let goal = map.wrap(goal);
+            *field.integration.get_mut_untracked(goal) = 0;
+            frontier.push(Reverse((0, goal.q, goal.r)));
+        }
+        let changed = field.propagate(map, frontier);
+        field.update_directions(map, changed);
+        field
+    }
+
+    pub fn goals(&self) -> &GoalSet {
+        &self.goals
+    }
+
+    /// Total cost to reach a goal from `pos`, `None` if no goal is reachable
+    pub fn cost_to_goal(&self, pos: HexPos) -> Option<u32> {
+        match *self.integration.get(self.integration.wrap(pos)) {
+            UNREACHABLE => None,
+            cost => Some(cost),
+        }
+    }
+
+    /// The neighbour to step to from `pos`, `None` at a goal or if no goal is reachable
+    pub fn next_step(&self, pos: HexPos) -> Option<HexPos> {
+        let pos = self.directions.wrap(pos);
+        let direction = (*self.directions.get(pos))?;
+        pos.neighbors()
+            .nth(direction as usize)
+            .map(|next| self.directions.wrap(next))
+    }
+
+    /// Brings the field up to date with any tiles whose cost changed since the last update.
+    /// Only the part of the field that routed through the changed tiles is recomputed.
+    pub fn update(&mut self, map: &HexMap<MyTileData>) {
+        let changed_tiles = map
+            .positions()
+            .filter(|&pos| *self.keys.get(pos) != step_key(map.get(pos)))
+            .collect::<Vec<_>>();
+        if changed_tiles.is_empty() {
+            return;
+        }
+
+        // changed tiles' own routes, hexes whose best route stepped onto a changed tile, plus
+        // everything routing through them
+        let mut invalid = HashSet::new();
+        let mut stack = vec![];
+        for &pos in changed_tiles.iter() {
+            *self.keys.get_mut_untracked(pos) = step_key(map.get(pos));
+            if !self.is_goal(pos) {
+                stack.push(pos);
+            }
+            stack.extend(
+                self.integration
+                    .wrapped_neighbors(pos)
+                    .filter(|&neighbor| self.next_step(neighbor) == Some(pos)),
+            );
+        }
+        let mut upstream = HashMap::<HexPos, Vec<HexPos>>::new();
+        for pos in self.integration.positions() {
+            if let Some(next) = self.next_step(pos) {
+                upstream.entry(next).or_default().push(pos);
+            }
+        }
+        while let Some(pos) = stack.pop() {
+            if invalid.insert(pos) {
+                stack.extend(upstream.get(&pos).into_iter().flatten());
+            }
+        }
+
+        for &pos in invalid.iter() {
+            *self.integration.get_mut_untracked(pos) = UNREACHABLE;
+            *self.directions.get_mut_untracked(pos) = None;
+        }
+
+        // restart the search from everything still valid that borders the invalidated area,
+        // and from the changed tiles themselves in case they got cheaper
+        let mut frontier = BinaryHeap::new();
+        for &pos in invalid.iter().chain(changed_tiles.iter()) {
+            for pos in std::iter::once(pos).chain(self.integration.wrapped_neighbors(pos)) {
+                let cost = *self.integration.get(pos);
+                if cost != UNREACHABLE {
+                    frontier.push(Reverse((cost, pos.q, pos.r)));
+                }
+            }
+        }
+        let mut changed = self.propagate(map, frontier);
+        changed.extend(invalid);
+        changed.extend(changed_tiles);
+        self.update_directions(map, changed);
+    }
+
+    fn is_goal(&self, pos: HexPos) -> bool {
+        (self.goals.0.iter()).any(|&goal| self.integration.wrap(goal) == pos)
+    }
+
+    /// `step_cost`, except that goals can be impassable (e.g. a lake) and still be walked
+    /// towards, agents stop next to those
+    fn edge_cost(&self, map: &HexMap<MyTileData>, from: HexPos, to: HexPos) -> Option<u32> {
+        step_cost(map.get(from), map.get(to)).or_else(|| {
+            let stands_on = tile_cost(map.get(to)).is_some();
+            (self.is_goal(to) && !stands_on).then_some(1)
+        })
+    }
+
+    /// Dijkstra outwards from `frontier`, returns every hex whose cost went down
+    fn propagate(
+        &mut self,
+        map: &HexMap<MyTileData>,
+        mut frontier: BinaryHeap<Reverse<(u32, i32, i32)>>,
+    ) -> HashSet<HexPos> {
+        let mut changed = HashSet::new();
+        while let Some(Reverse((cost, q, r))) = frontier.pop() {
+            let pos = HexPos { q, r };
+            if cost > *self.integration.get(pos) {
+                continue;
+            }
+            for neighbor in self.integration.wrapped_neighbors(pos) {
+                let Some(step_cost) = self.edge_cost(map, neighbor, pos) else {
+                    continue;
+                };
+                let new_cost = cost + step_cost;
+                if new_cost < *self.integration.get(neighbor) {
+                    *self.integration.get_mut_untracked(neighbor) = new_cost;
+                    changed.insert(neighbor);
+                    frontier.push(Reverse((new_cost, neighbor.q, neighbor.r)));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Recomputes the best direction of every hex in `changed` and their neighbours
+    fn update_directions(&mut self, map: &HexMap<MyTileData>, changed: HashSet<HexPos>) {
+        let mut dirty = HashSet::new();
+        for pos in changed {
+            dirty.insert(pos);
+            dirty.extend(self.integration.wrapped_neighbors(pos));
+        }
+
+        for pos in dirty {
+            let here = *self.integration.get(pos);
+            let best = self
+                .integration
+                .wrapped_neighbors(pos)
+                .enumerate()
+                .filter(|&(_, neighbor)| *self.integration.get(neighbor) != UNREACHABLE)
+                .filter_map(|(direction, neighbor)| {
+                    let step_cost = self.edge_cost(map, pos, neighbor)?;
+                    Some((direction, self.integration.get(neighbor) + step_cost))
+                })
+                .min_by_key(|&(_, cost)| cost);
+            *self.directions.get_mut_untracked(pos) = match here {
+                0 | UNREACHABLE => None,
+                _ => best.map(|(direction, _)| direction as u8),
+            };
+        }
+    }
+}
+
+/// Flow fields for every goal set in use on a surface
+// Not Inspectable because of HashMap
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FlowFields {
+    fields: HashMap<GoalSet, FlowField>,
+}
+
+impl FlowFields {
+    pub fn get(&self, goals: &GoalSet) -> Option<&FlowField> {
+        self.fields.get(goals)
+    }
+
+    /// Gets the field for `goals`, generating it if nothing has asked for it lately. Fields
+    /// nothing gets this way for `FIELD_IDLE_TICKS` are dropped.
+    pub fn get_or_insert(&mut self, goals: &GoalSet, map: &HexMap<MyTileData>) -> &FlowField {
+        let field = self
+            .fields
+            .entry(goals.clone())
+            .or_insert_with(|| FlowField::new(goals.clone(), map));
+        field.used = true;
+        field
+    }
+}
+
+/// Agents with this head for the nearest of the goals by following the shared flow field
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct FlowGoal(pub GoalSet);
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| world.init_resource::<FlowFields>())
+        .push_state_resource::<FlowFields>()
+        .push_state_component::<FlowGoal>()
+        .push_system(SurfaceSystem::new(update_flow_fields).in_stage(SurfaceStage::PreSim))
+        .push_system(follow_flow_fields);
+}
+
+fn update_flow_fields(map: Res<HexMap<MyTileData>>, mut fields: ResMut<FlowFields>) {
+    fields.fields.retain(|_, field| {
+        field.idle_ticks = match std::mem::take(&mut field.used) {
+            true => 0,
+            false => field.idle_ticks + 1,
+        };
+        field.idle_ticks < FIELD_IDLE_TICKS
+    });
+    for field in fields.fields.values_mut() {
+        field.update(&map);
+    }
+}
+
+fn follow_flow_fields(
+    mut cmds: Commands<'_, '_>,
+    map: Res<HexMap<MyTileData>>,
+    mut fields: ResMut<FlowFields>,
+    agents: Query<(Entity, &HexPos, &FlowGoal), Without<Destination>>,
+) {
+    for (agent, &pos, goal) in agents.iter() {
+        let next = fields.get_or_insert(&goal.0, &map).next_step(pos);
+        match next.filter(|&next| can_step(map.get(pos), map.get(next))) {
+            Some(next) => cmds.entity(agent).insert(Destination(next)),
+            // at a goal, next to one that can't be stood on, or no goal is reachable
+            None => cmds.entity(agent).remove::<FlowGoal>(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agents::spawn_agent, geology::Lava, rng::Rng, vegetation::Vegetation, wildfire::Fire,
+    };
+
+    fn tile(kind: TileKind, height: u8) -> MyTileData {
+        MyTileData {
+            height,
+            kind,
+            vegetation: Vegetation::default(),
+            fire: Fire::default(),
+            deposit: None,
+            lava: Lava::default(),
+            snow: false,
+        }
+    }
+
+    fn random_tile(rng: &mut Rng) -> MyTileData {
+        let kind = match rng.chance(0.2) {
+            true => TileKind::Water,
+            false => TileKind::Rock,
+        };
+        tile(kind, rng.below(4) as u8)
+    }
+
+    fn assert_same(a: &FlowField, b: &FlowField, map: &HexMap<MyTileData>) {
+        for pos in map.positions() {
+            assert_eq!(a.cost_to_goal(pos), b.cost_to_goal(pos), "cost at {pos:?}");
+            assert_eq!(a.next_step(pos), b.next_step(pos), "step at {pos:?}");
+        }
+    }
+
+    #[test]
+    fn updates_match_a_fresh_field() {
+        let mut rng = Rng::from_seed(31);
+        let mut map = HexMap::new(12, 10, (0..12 * 10).map(|_| random_tile(&mut rng)));
+        let goals = GoalSet::new([HexPos { q: 2, r: 3 }, HexPos { q: 9, r: 7 }]);
+        let mut field = FlowField::new(goals.clone(), &map);
+        for _ in 0..300 {
+            for _ in 0..=rng.below(4) {
+                let pos = HexPos {
+                    q: rng.below(12) as i32,
+                    r: rng.below(10) as i32,
+                };
+                *map.get_mut(pos) = random_tile(&mut rng);
+            }
+            field.update(&map);
+            assert_same(&field, &FlowField::new(goals.clone(), &map), &map);
+        }
+    }
+
+    #[test]
+    fn fields_wrap_around_the_edges() {
+        let map = HexMap::new(8, 6, vec![tile(TileKind::Rock, 0); 8 * 6]);
+        let goal = HexPos { q: 0, r: 0 };
+        let field = FlowField::new(GoalSet::new([goal]), &map);
+        let step = tile_cost(map.get(goal)).unwrap();
+        for across in [HexPos { q: 7, r: 0 }, HexPos { q: 0, r: 5 }] {
+            assert_eq!(field.next_step(across), Some(goal), "from {across:?}");
+            assert_eq!(field.cost_to_goal(across), Some(step), "from {across:?}");
+        }
+        // off the map counts as the hex it wraps to
+        assert_eq!(field.next_step(HexPos { q: -1, r: 0 }), Some(goal));
+    }
+
+    #[test]
+    fn crowds_follow_the_field_around_walls() {
+        // walls across the map with one gap, on both sides so going round the edge doesn't help
+        let (width, height) = (12, 6);
+        let map = HexMap::new(
+            width,
+            height,
+            (0..width * height).map(|idx| {
+                let (q, r) = (idx % width, idx / width);
+                match (q == 5 && r != 2) || q == 11 {
+                    true => tile(TileKind::Rock, 3),
+                    false => tile(TileKind::Rock, 0),
+                }
+            }),
+        );
+        let goal = HexPos { q: 8, r: 4 };
+        let mut surfaces = Surfaces::new(0);
+        crate::agents::add_systems(&mut surfaces);
+        add_systems(&mut surfaces);
+        let mut world = World::new();
+        let crowd = (0..height as i32)
+            .map(|r| {
+                let agent = spawn_agent(&mut world, HexPos { q: 2, r });
+                world
+                    .entity_mut(agent)
+                    .insert(FlowGoal(GoalSet::new([goal])));
+                agent
+            })
+            .collect::<Vec<_>>();
+        let id = surfaces.new_surface(world, map);
+
+        for _ in 0..300 {
+            surfaces.simulate_step();
+        }
+        let world = surfaces.get(id).unwrap();
+        for agent in crowd {
+            assert_eq!(world.get::<HexPos>(agent), Some(&goal));
+            assert!(world.get::<FlowGoal>(agent).is_none());
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    draw::{Action, HoveredHex},
+    hexmap::{HexMap, HexPos},
+    rng::{Rng, SurfaceRng},
+    schedule::{SurfaceStage, SurfaceSystem},
+    simulation::{MyTileData, TileKind},
+    surfaces::{CurrentSurface, Surfaces},
+    vegetation::Vegetation,
+    wildfire::{self, Fire, Ignitions},
+    AppState,
+};
+
+/// Lava thinner than this is too sticky to flow any further
+const MIN_FLOW_DEPTH: f32 = 0.2;
+/// Fraction of the difference in lava surface height that flows to a lower neighbour per tick
+const VISCOSITY: f32 = 0.05;
+const COOLING_PER_TICK: f32 = 0.002;
+const ERUPTION_LAVA_PER_TICK: f32 = 0.05;
+/// An erupting vent builds itself up one height every this many ticks
+const VENT_GROWTH_TICKS: u32 = 200;
+/// Tectonics won't push the ground any higher than this
+const MAX_HEIGHT: u8 = 10;
+/// Per tick chance of a dormant volcano waking up
+const ERUPTION_CHANCE: f32 = 0.0002;
+const ERUPTION_TICKS: u32 = 600;
+/// Per tick chance of a new volcano opening up somewhere along a fault line
+const NEW_VOLCANO_CHANCE: f32 = 0.000_05;
+/// Fault lines every surface starts out with, more can be added to `FaultLines`
+const DEFAULT_FAULTS: usize = 2;
+/// Height change per tick along the default fault lines
+const DEFAULT_FAULT_RATE: f32 = 0.0005;
+
+/// Molten rock sitting on top of a tile
+#[derive(Copy, Clone, Debug, Default, PartialEq, Inspectable, Serialize, Deserialize)]
+pub struct Lava {
+    /// In the same units as `MyTileData::height`
+    pub depth: f32,
+    /// `1.0` fresh out of the vent, turns to rock at `0.0`
+    pub heat: f32,
+}
+
+impl Lava {
+    pub fn is_molten(self) -> bool {
+        self.depth > 0.0
+    }
+}
+
+/// A vent on its hex, always paired with a `HexPos`
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Volcano {
+    /// `0` when dormant
+    pub erupting_ticks: u32,
+}
+
+/// The ground along `from`..`to` slowly moves up (positive `rate`) or down (negative `rate`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultLine {
+    pub from: HexPos,
+    pub to: HexPos,
+    /// Height per tick
+    pub rate: f32,
+    /// Movement that hasn't added up to a whole height step yet
+    pub accumulated: f32,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FaultLines(pub Vec<FaultLine>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GeologyEvent {
+    /// Erupts the volcano at `pos` for `ticks`, opening a new one there if there isn't one
+    Erupt { pos: HexPos, ticks: u32 },
+    /// Moves the ground along a fault `steps` heights in one go
+    Quake { fault: usize, steps: i32 },
+}
+
+/// Events to apply next tick, for user actions and scenario scripts
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct GeologyEvents(pub Vec<GeologyEvent>);
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+enum GeologySystem {
+    Erupt,
+}
+
+pub fn init_app(app: &mut App) {
+    app.add_system(erupt_clicked.run_in_state(AppState::Playing));
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| {
+            world.init_resource::<GeologyEvents>();
+            let faults = world.resource_scope(|world, mut rng: Mut<SurfaceRng>| {
+                let rng = rng.stream("geology");
+                let map = world.resource::<HexMap<MyTileData>>();
+                let random_pos = |rng: &mut Rng| HexPos {
+                    q: rng.below(map.width() as u32) as i32,
+                    r: rng.below(map.height() as u32) as i32,
+                };
+                (0..DEFAULT_FAULTS)
+                    .map(|i| FaultLine {
+                        from: random_pos(rng),
+                        to: random_pos(rng),
+                        // alternate so the map doesn't just keep rising
+                        rate: match i % 2 {
+                            0 => DEFAULT_FAULT_RATE,
+                            _ => -DEFAULT_FAULT_RATE,
+                        },
+                        accumulated: 0.0,
+                    })
+                    .collect()
+            });
+            world.insert_resource(FaultLines(faults));
+        })
+        .push_state_resource::<GeologyEvents>()
+        .push_state_resource::<FaultLines>()
+        .push_state_component::<Volcano>()
+        .push_system(
+            SurfaceSystem::new(apply_geology_events)
+                .in_stage(SurfaceStage::PreSim)
+                .when_dormant(),
+        )
+        .push_system(SurfaceSystem::new(move_faults).when_dormant())
+        .push_system(
+            SurfaceSystem::new(erupt_volcanoes)
+                .label(GeologySystem::Erupt)
+                .when_dormant(),
+        )
+        .push_system(
+            SurfaceSystem::new(flow_lava)
+                .after(GeologySystem::Erupt)
+                .when_dormant(),
+        )
+        .push_metric("lava tiles", lava_tiles);
+}
+
+fn lava_tiles(map: Res<HexMap<MyTileData>>) -> f32 {
+    map.positions()
+        .filter(|&pos| map.get(pos).lava.is_molten())
+        .count() as f32
+}
+
+/// Right clicking a hex on the selected surface starts an eruption there
+fn erupt_clicked(
+    actions: Query<&ActionState<Action>, With<Camera>>,
+    hovered: Res<HoveredHex>,
+    mut surface: CurrentSurface<'_, '_>,
+) {
+    if !actions.single().just_pressed(Action::Erupt) {
+        return;
+    }
+    if let (Some(pos), Some(world)) = (hovered.0, surface.world_mut()) {
+        world
+            .resource_mut::<GeologyEvents>()
+            .0
+            .push(GeologyEvent::Erupt {
+                pos,
+                ticks: ERUPTION_TICKS,
+            });
+    }
+}
+
+fn apply_geology_events(
+    mut cmds: Commands<'_, '_>,
+    mut map: ResMut<HexMap<MyTileData>>,
+    mut events: ResMut<GeologyEvents>,
+    mut faults: ResMut<FaultLines>,
+    mut volcanoes: Query<(&HexPos, &mut Volcano)>,
+) {
+    for event in events.0.drain(..) {
+        match event {
+            GeologyEvent::Erupt { pos, ticks } => {
+                let pos = map.wrap(pos);
+                match volcanoes.iter_mut().find(|(&vent, _)| vent == pos) {
+                    Some((_, mut volcano)) => volcano.erupting_ticks = ticks,
+                    None => {
+                        cmds.spawn().insert_bundle((
+                            pos,
+                            Volcano {
+                                erupting_ticks: ticks,
+                            },
+                        ));
+                    }
+                }
+            }
+            GeologyEvent::Quake { fault, steps } => {
+                if let Some(fault) = faults.0.get_mut(fault) {
+                    shift_fault(&mut map, fault, steps);
+                }
+            }
+        }
+    }
+}
+
+fn shift_fault(map: &mut HexMap<MyTileData>, fault: &FaultLine, steps: i32) {
+    for pos in fault.from.line_to(fault.to) {
+        let tile = map.get_mut(map.wrap(pos));
+        tile.height = (tile.height as i32 + steps).clamp(0, MAX_HEIGHT as i32) as u8;
+    }
+}
+
+fn move_faults(mut map: ResMut<HexMap<MyTileData>>, mut faults: ResMut<FaultLines>) {
+    for fault in faults.0.iter_mut() {
+        fault.accumulated += fault.rate;
+        let steps = fault.accumulated.trunc();
+        if steps != 0.0 {
+            fault.accumulated -= steps;
+            shift_fault(&mut map, fault, steps as i32);
+        }
+    }
+}
+
+fn erupt_volcanoes(
+    mut cmds: Commands<'_, '_>,
+    mut map: ResMut<HexMap<MyTileData>>,
+    mut rng: ResMut<SurfaceRng>,
+    faults: Res<FaultLines>,
+    mut volcanoes: Query<(&HexPos, &mut Volcano)>,
+) {
+    let rng = rng.stream("geology");
+
+    for (&pos, mut volcano) in volcanoes.iter_mut() {
+        if volcano.erupting_ticks == 0 {
+            if rng.chance(ERUPTION_CHANCE) {
+                volcano.erupting_ticks = ERUPTION_TICKS;
+            }
+            continue;
+        }
+
+        volcano.erupting_ticks -= 1;
+        let tile = map.get_mut(pos);
+        tile.lava.depth += ERUPTION_LAVA_PER_TICK;
+        tile.lava.heat = 1.0;
+        if volcano.erupting_ticks % VENT_GROWTH_TICKS == 0 {
+            tile.height = tile.height.saturating_add(1);
+        }
+    }
+
+    if !faults.0.is_empty() && rng.chance(NEW_VOLCANO_CHANCE) {
+        let fault = &faults.0[rng.below(faults.0.len() as u32) as usize];
+        let line = fault.from.line_to(fault.to).collect::<Vec<_>>();
+        let pos = map.wrap(line[rng.below(line.len() as u32) as usize]);
+        cmds.spawn().insert_bundle((
+            pos,
+            Volcano {
+                erupting_ticks: ERUPTION_TICKS,
+            },
+        ));
+    }
+}
+
+/// Lava runs towards neighbours whose lava surface is lower, cools as it goes,
+/// and sets fire to anything it reaches that can burn
+fn flow_lava(mut map: ResMut<HexMap<MyTileData>>, mut ignitions: ResMut<Ignitions>) {
+    let surface = |tile: &MyTileData| tile.height as f32 + tile.lava.depth;
+
+    // work out every flow from the current map before writing anything so
+    // the result doesn't depend on iteration order
+    let mut next = map
+        .positions()
+        .map(|pos| map.get(pos).lava)
+        .collect::<Vec<_>>();
+    for pos in map.positions() {
+        let tile = map.get(pos);
+        if tile.lava.depth <= MIN_FLOW_DEPTH {
+            continue;
+        }
+        let flows = map
+            .wrapped_neighbors(pos)
+            .map(|neighbor| {
+                let drop = surface(tile) - surface(map.get(neighbor));
+                (neighbor, (drop * VISCOSITY).max(0.0))
+            })
+            .collect::<Vec<_>>();
+        let total = flows.iter().map(|&(_, amount)| amount).sum::<f32>();
+        if total <= 0.0 {
+            continue;
+        }
+        // never more than it has to spare
+        let scale = ((tile.lava.depth - MIN_FLOW_DEPTH) / total).min(1.0);
+        for (neighbor, amount) in flows {
+            let amount = amount * scale;
+            if amount <= 0.0 {
+                continue;
+            }
+            let to = &mut next[neighbor.q as usize + neighbor.r as usize * map.width()];
+            to.heat = (to.heat * to.depth + tile.lava.heat * amount) / (to.depth + amount);
+            to.depth += amount;
+            next[pos.q as usize + pos.r as usize * map.width()].depth -= amount;
+        }
+    }
+
+    let width = map.width();
+    for pos in map.positions() {
+        let mut lava = next[pos.q as usize + pos.r as usize * width];
+        if !lava.is_molten() {
+            continue;
+        }
+        lava.heat -= COOLING_PER_TICK;
+
+        let tile = map.get_mut(pos);
+        if wildfire::fuel(tile) > 0.0 && !tile.fire.is_burning() {
+            ignitions.0.push(pos);
+        }
+        // quenched by water, or cooled into new rock
+        if tile.kind == TileKind::Water || lava.heat <= 0.0 {
+            tile.height = tile.height.saturating_add(lava.depth.round() as u8);
+            tile.kind = TileKind::Rock;
+            tile.vegetation = Vegetation::default();
+            tile.fire = Fire::None;
+            tile.lava = Lava::default();
+        } else {
+            tile.lava = lava;
+        }
+    }
+}
@@ -1,6 +1,12 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::Component;
 use bevy_inspector_egui::Inspectable;
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Inspectable)]
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, Hash, Inspectable, Component, Serialize, Deserialize,
+)]
 pub struct HexPos {
     pub q: i32,
     pub r: i32,
@@ -10,10 +16,10 @@ impl HexPos {
     pub fn neighbors(self) -> impl Iterator<Item = HexPos> {
         [
             HexPos { q: 1, r: 0 },
-            HexPos { q: 1, r: 1 },
+            HexPos { q: 1, r: -1 },
             HexPos { q: -1, r: 0 },
-            HexPos { q: -1, r: -1 },
-            HexPos { q: 0, r: -1 },
+            HexPos { q: -1, r: 1 },
+            HexPos { q: 0, r: 1 },
             HexPos { q: 0, r: -1 },
         ]
         .into_iter()
@@ -22,15 +28,83 @@ impl HexPos {
             r: pos.r + self.r,
         })
     }
+
+    /// Number of steps between two hexes, ignoring map wrapping
+    pub fn distance(self, other: HexPos) -> u32 {
+        let dq = self.q - other.q;
+        let dr = self.r - other.r;
+        (dq.unsigned_abs() + dr.unsigned_abs() + (dq + dr).unsigned_abs()) / 2
+    }
+
+    /// Every hex on a straight line from `self` to `other` inclusive, ignoring map wrapping
+    pub fn line_to(self, other: HexPos) -> impl Iterator<Item = HexPos> {
+        let steps = self.distance(other);
+        (0..=steps).map(move |step| {
+            // nudged a little so lines running exactly along hex edges pick a consistent side
+            let t = match steps {
+                0 => 0.0,
+                _ => step as f32 / steps as f32,
+            };
+            let q = self.q as f32 + 1e-6 + (other.q - self.q) as f32 * t;
+            let r = self.r as f32 + 1e-6 + (other.r - self.r) as f32 * t;
+            round_hex(q, r)
+        })
+    }
+}
+
+/// Nearest hex to fractional axial coordinates
+fn round_hex(q: f32, r: f32) -> HexPos {
+    let s = -q - r;
+    let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+        rq = -rr - rs;
+    } else if dr > ds {
+        rr = -rq - rs;
+    }
+    HexPos {
+        q: rq as i32,
+        r: rr as i32,
+    }
 }
 
 // dont `derive(Default)` the `tiles` field will have length 0
 // Not Inspectable because of Box<[T]>
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct HexMap<T> {
     width: usize,
     height: usize,
     tiles: Box<[T]>,
+    /// Whether each tile has been handed out by `get_mut` since the last
+    /// `take_changes`, empty until the first tracked write
+    #[serde(skip)]
+    dirty: Box<[bool]>,
+    /// Index and value from before the first `get_mut` of every dirty tile
+    #[serde(skip)]
+    changes: Vec<(usize, T)>,
+}
+
+/// The fields of a `HexMap` that get saved
+#[derive(Deserialize)]
+struct SavedHexMap<T> {
+    width: usize,
+    height: usize,
+    tiles: Vec<T>,
+}
+
+// by hand so tiles that don't fit the size are an error instead of a panic in `HexMap::new`
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for HexMap<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let SavedHexMap {
+            width,
+            height,
+            tiles,
+        } = SavedHexMap::deserialize(deserializer)?;
+        if width.checked_mul(height) != Some(tiles.len()) {
+            return Err(D::Error::custom("hex map tiles don't fit its size"));
+        }
+        Ok(Self::new(width, height, tiles))
+    }
 }
 
 impl<T> HexMap<T> {
@@ -45,12 +119,14 @@ impl<T> HexMap<T> {
     pub fn new(width: usize, height: usize, tiles: impl IntoIterator<Item = T>) -> Self {
         let mut tiles_iter = tiles.into_iter();
         let tiles = (&mut tiles_iter).take(width * height).collect::<Box<[T]>>();
-        assert!(matches!(tiles_iter.next(), None));
+        assert!(tiles_iter.next().is_none());
         assert_eq!(tiles.len(), width * height);
         Self {
             width,
             height,
             tiles,
+            dirty: Box::new([]),
+            changes: vec![],
         }
     }
 
@@ -60,18 +136,132 @@ impl<T> HexMap<T> {
         assert!((pos.q as usize) < self.width);
         assert!((pos.r as usize) < self.height);
 
-        let foo = pos.q as usize + ((pos.r as usize) * self.width);
-        &self.tiles[foo]
+        let idx = pos.q as usize + ((pos.r as usize) * self.width);
+        &self.tiles[idx]
+    }
+
+    /// `get_mut` without the change tracking, for scratch maps nothing watches for changes
+    pub fn get_mut_untracked(&mut self, pos: HexPos) -> &mut T {
+        assert!(pos.q >= 0);
+        assert!(pos.r >= 0);
+        assert!((pos.q as usize) < self.width);
+        assert!((pos.r as usize) < self.height);
+
+        let idx = pos.q as usize + ((pos.r as usize) * self.width);
+        &mut self.tiles[idx]
+    }
+
+    /// Whether `get_mut` has been called on `pos` since the last `take_changes`, the
+    /// tile may still have been set back to what it was
+    pub fn is_dirty(&self, pos: HexPos) -> bool {
+        let idx = pos.q as usize + ((pos.r as usize) * self.width);
+        self.dirty.get(idx).copied().unwrap_or(false)
+    }
+
+    /// Every tile `get_mut` has been called on since the last `take_changes`
+    pub fn dirty(&self) -> impl Iterator<Item = HexPos> + '_ {
+        let width = self.width;
+        self.changes.iter().map(move |&(idx, _)| HexPos {
+            q: (idx % width) as i32,
+            r: (idx / width) as i32,
+        })
+    }
+
+    /// Wraps `pos` around the edges of this map
+    pub fn wrap(&self, pos: HexPos) -> HexPos {
+        wrap_hex_pos(pos, self.width as u32, self.height as u32)
+    }
+
+    /// Like `HexPos::neighbors` but wrapped around the edges of this map
+    pub fn wrapped_neighbors(&self, pos: HexPos) -> impl Iterator<Item = HexPos> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        pos.neighbors()
+            .map(move |pos| wrap_hex_pos(pos, width, height))
+    }
+
+    /// Number of steps between two hexes going the short way around the map edges
+    pub fn wrapped_distance(&self, a: HexPos, b: HexPos) -> u32 {
+        let (a, b) = (self.wrap(a), self.wrap(b));
+        let (width, height) = (self.width as i32, self.height as i32);
+        [-width, 0, width]
+            .into_iter()
+            .flat_map(|dq| [-height, 0, height].map(|dr| (dq, dr)))
+            .map(|(dq, dr)| {
+                a.distance(HexPos {
+                    q: b.q + dq,
+                    r: b.r + dr,
+                })
+            })
+            .min()
+            .unwrap()
+    }
+
+    /// Every hex within `max_steps` of `from` (wrapping around the edges) with its distance, nearest first
+    pub fn within(&self, from: HexPos, max_steps: u32) -> impl Iterator<Item = (HexPos, u32)> {
+        let from = self.wrap(from);
+        let mut seen = HashSet::from([from]);
+        let mut found = vec![];
+        let mut queue = VecDeque::from([(from, 0)]);
+        while let Some((pos, distance)) = queue.pop_front() {
+            found.push((pos, distance));
+            if distance == max_steps {
+                continue;
+            }
+            for next in self.wrapped_neighbors(pos) {
+                if seen.insert(next) {
+                    queue.push_back((next, distance + 1));
+                }
+            }
+        }
+        found.into_iter()
     }
 
+    /// Every position in the map, row by row
+    pub fn positions(&self) -> impl Iterator<Item = HexPos> {
+        let width = self.width;
+        (0..self.width * self.height).map(move |idx| HexPos {
+            q: (idx % width) as i32,
+            r: (idx / width) as i32,
+        })
+    }
+}
+
+impl<T: Clone> HexMap<T> {
+    /// Marks the tile dirty, remembering its old value the first time so `take_changes` can
+    /// report it. Use `get_mut_untracked` on maps nothing watches for changes.
     pub fn get_mut(&mut self, pos: HexPos) -> &mut T {
         assert!(pos.q >= 0);
         assert!(pos.r >= 0);
         assert!((pos.q as usize) < self.width);
         assert!((pos.r as usize) < self.height);
 
-        let foo = pos.q as usize + ((pos.r as usize) * self.width);
-        &mut self.tiles[foo]
+        let idx = pos.q as usize + ((pos.r as usize) * self.width);
+        if self.dirty.is_empty() {
+            self.dirty = vec![false; self.tiles.len()].into_boxed_slice();
+        }
+        if !self.dirty[idx] {
+            self.dirty[idx] = true;
+            self.changes.push((idx, self.tiles[idx].clone()));
+        }
+        &mut self.tiles[idx]
+    }
+
+    /// Every dirty tile along with its value from before it was first made dirty, in the
+    /// order they were dirtied. Clears the dirty set.
+    pub fn take_changes(&mut self) -> impl Iterator<Item = (HexPos, T)> {
+        let width = self.width;
+        for &(idx, _) in self.changes.iter() {
+            self.dirty[idx] = false;
+        }
+        std::mem::take(&mut self.changes)
+            .into_iter()
+            .map(move |(idx, old)| {
+                let pos = HexPos {
+                    q: (idx % width) as i32,
+                    r: (idx / width) as i32,
+                };
+                (pos, old)
+            })
     }
 }
 
@@ -0,0 +1,377 @@
+use std::collections::VecDeque;
+
+use bevy::{ecs::event::ManualEventReader, prelude::*};
+use bevy_inspector_egui::{bevy_egui::EguiContext, egui};
+use iyes_loopless::prelude::*;
+
+use crate::{
+    hexmap::{HexMap, HexPos},
+    simulation::{MyTileData, SurfaceTileChanged, TileChanged},
+    snapshot::{self, SavedEntity, SavedValues, StateTypes},
+    surfaces::{SelectedSurface, SurfaceId, Surfaces},
+    AppState,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct HistorySettings {
+    /// Ticks between keyframes, fewer means faster scrubbing but more memory
+    pub keyframe_every: u64,
+    /// Bytes of keyframes and deltas to keep before the oldest get dropped
+    pub memory_budget: usize,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            keyframe_every: 100,
+            memory_budget: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// The surface's whole state at the start of `tick`, the same way a save stores it
+pub struct Keyframe {
+    pub tick: u64,
+    pub resources: SavedValues,
+    pub entities: Vec<SavedEntity>,
+    pub free_entities: Vec<u64>,
+}
+
+impl Keyframe {
+    fn size(&self) -> usize {
+        let values = |values: &SavedValues| {
+            (values.iter())
+                .map(|(name, value)| name.len() + value.len())
+                .sum::<usize>()
+        };
+        values(&self.resources)
+            + (self.entities.iter())
+                .map(|entity| std::mem::size_of::<SavedEntity>() + values(&entity.components))
+                .sum::<usize>()
+            + self.free_entities.len() * std::mem::size_of::<u64>()
+    }
+}
+
+/// Tiles set during one tick, to their new value
+type TileDelta = Vec<(HexPos, MyTileData)>;
+
+fn delta_size(delta: &TileDelta) -> usize {
+    std::mem::size_of::<TileDelta>() + delta.len() * std::mem::size_of::<(HexPos, MyTileData)>()
+}
+
+/// A ring buffer of a surface's recent past, see `Surfaces::set_history`. Keyframes hold
+/// everything and are taken every `keyframe_every` ticks, in between only the map's changes are
+/// kept. Once over `memory_budget` the oldest keyframe and the deltas after it get dropped.
+// Not Inspectable because of the event reader
+pub struct History {
+    settings: HistorySettings,
+    /// Oldest first, never empty once the first step has been recorded
+    keyframes: VecDeque<Keyframe>,
+    /// One per tick starting at `first_delta_tick`, which is the oldest keyframe's tick
+    deltas: VecDeque<TileDelta>,
+    first_delta_tick: u64,
+    tile_changes: ManualEventReader<TileChanged>,
+    size: usize,
+}
+
+impl History {
+    /// Only changes sent after this get recorded
+    pub fn new(settings: HistorySettings, world: &World) -> Self {
+        Self {
+            settings,
+            keyframes: VecDeque::new(),
+            deltas: VecDeque::new(),
+            first_delta_tick: 0,
+            tile_changes: world.resource::<Events<TileChanged>>().get_reader_current(),
+            size: 0,
+        }
+    }
+
+    pub fn settings(&self) -> HistorySettings {
+        self.settings
+    }
+
+    /// Bytes used by keyframes and deltas, roughly
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The ticks that can be looked at, the last one being the surface's current tick.
+    /// `None` before anything has been recorded.
+    pub fn range(&self) -> Option<(u64, u64)> {
+        let oldest = self.keyframes.front()?.tick;
+        Some((oldest, self.first_delta_tick + self.deltas.len() as u64))
+    }
+
+    pub fn contains(&self, tick: u64) -> bool {
+        matches!(self.range(), Some((oldest, latest)) if (oldest..=latest).contains(&tick))
+    }
+
+    /// The latest keyframe at or before `tick`
+    pub fn keyframe_before(&self, tick: u64) -> Option<&Keyframe> {
+        self.keyframes
+            .iter()
+            .rev()
+            .find(|keyframe| keyframe.tick <= tick)
+    }
+
+    /// Tiles that changed going from `tick` to the next one
+    pub fn delta(&self, tick: u64) -> Option<&[(HexPos, MyTileData)]> {
+        let index = tick.checked_sub(self.first_delta_tick)?;
+        self.deltas.get(index as usize).map(|delta| &delta[..])
+    }
+
+    /// What the map looked like at the start of `tick`, `None` if it's not in the history
+    pub fn map_at(&self, tick: u64) -> Option<HexMap<MyTileData>> {
+        if !self.contains(tick) {
+            return None;
+        }
+        let keyframe = self.keyframe_before(tick)?;
+        let (_, map) = (keyframe.resources.iter())
+            .find(|(name, _)| name == std::any::type_name::<HexMap<MyTileData>>())?;
+        let mut map = match ron::from_str::<HexMap<MyTileData>>(map) {
+            Ok(map) => map,
+            Err(err) => {
+                error!("couldn't read the map of keyframe {}: {err}", keyframe.tick);
+                return None;
+            }
+        };
+        for tick in keyframe.tick..tick {
+            for (pos, tile) in self.delta(tick)? {
+                *map.get_mut_untracked(*pos) = tile.clone();
+            }
+        }
+        Some(map)
+    }
+
+    /// Called before stepping, takes a keyframe if one is due
+    pub fn record_keyframe(&mut self, tick: u64, world: &World, state_types: &StateTypes) {
+        if !self.keyframes.is_empty() && !tick.is_multiple_of(self.settings.keyframe_every.max(1)) {
+            return;
+        }
+        let saved = state_types
+            .save_resources(world)
+            .and_then(|resources| Ok((resources, state_types.save_entities(world)?)));
+        let (resources, entities) = match saved {
+            Ok(saved) => saved,
+            Err(err) => {
+                error!("couldn't take a keyframe at tick {tick}: {err}");
+                return;
+            }
+        };
+        if self.keyframes.is_empty() {
+            self.first_delta_tick = tick;
+        }
+        let keyframe = Keyframe {
+            tick,
+            resources,
+            entities,
+            free_entities: (snapshot::free_entities(world).iter())
+                .map(|entity| entity.to_bits())
+                .collect(),
+        };
+        self.size += keyframe.size();
+        self.keyframes.push_back(keyframe);
+    }
+
+    /// Called after stepping, keeps the tiles the step changed
+    pub fn record_tick(&mut self, world: &World) {
+        let events = world.resource::<Events<TileChanged>>();
+        let delta = (self.tile_changes.iter(events))
+            .map(|change| (change.pos, change.new.clone()))
+            .collect::<TileDelta>();
+        if self.keyframes.is_empty() {
+            return;
+        }
+        self.size += delta_size(&delta);
+        self.deltas.push_back(delta);
+
+        while self.size > self.settings.memory_budget && self.keyframes.len() > 1 {
+            let oldest = self.keyframes.pop_front().unwrap();
+            self.size -= oldest.size();
+            let next = self.keyframes.front().unwrap().tick;
+            while self.first_delta_tick < next {
+                let delta = self.deltas.pop_front().unwrap();
+                self.size -= delta_size(&delta);
+                self.first_delta_tick += 1;
+            }
+        }
+    }
+
+    /// Forgets everything from the keyframe at or before `tick` onwards and gives that keyframe
+    /// back, for rewinding the surface to it
+    pub fn truncate_to_keyframe(&mut self, tick: u64) -> Option<Keyframe> {
+        if !self.contains(tick) {
+            return None;
+        }
+        let index = self
+            .keyframes
+            .iter()
+            .rposition(|keyframe| keyframe.tick <= tick)?;
+        let mut dropped = self.keyframes.split_off(index);
+        let keyframe = dropped.pop_front()?;
+        self.size -= keyframe.size() + dropped.iter().map(Keyframe::size).sum::<usize>();
+        let kept = (keyframe.tick - self.first_delta_tick) as usize;
+        for delta in self.deltas.drain(kept..) {
+            self.size -= delta_size(&delta);
+        }
+        Some(keyframe)
+    }
+
+    /// Starts reading tile changes from a fresh world, after the surface has been rewound
+    pub fn reset_reader(&mut self, world: &World) {
+        self.tile_changes = world.resource::<Events<TileChanged>>().get_reader_current();
+    }
+}
+
+/// A past tick of `surface` being looked at in the History window
+struct ViewedTick {
+    surface: SurfaceId,
+    tick: u64,
+    map: HexMap<MyTileData>,
+    /// Play it forward a tick a frame
+    watching: bool,
+}
+
+/// What the History window is showing. While it's looking at the past the simulation doesn't step
+/// and the selected surface is drawn with the past map instead.
+#[derive(Default)]
+pub struct Timeline {
+    viewing: Option<ViewedTick>,
+}
+
+impl Timeline {
+    /// The map to draw for `surface`, if a past tick of it is being looked at
+    pub fn map(&self, surface: SurfaceId) -> Option<&HexMap<MyTileData>> {
+        (self.viewing.as_ref())
+            .filter(|viewing| viewing.surface == surface)
+            .map(|viewing| &viewing.map)
+    }
+}
+
+/// Run condition for stepping the simulation, it stays put while the past is being looked at
+pub fn is_live(timeline: Res<Timeline>) -> bool {
+    timeline.viewing.is_none()
+}
+
+pub fn init_app(app: &mut App) {
+    app.init_resource::<Timeline>()
+        .add_system(history_window.run_in_state(AppState::Playing));
+}
+
+/// Turns history on and off for the selected surface and scrubs through it
+fn history_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut surfaces: ResMut<Surfaces>,
+    mut selected: ResMut<SelectedSurface>,
+    mut timeline: ResMut<Timeline>,
+    mut tile_changes: EventWriter<'_, '_, SurfaceTileChanged>,
+) {
+    let surface = selected.0;
+    if (timeline.viewing.as_ref()).is_some_and(|viewing| viewing.surface != surface) {
+        timeline.viewing = None;
+    }
+    if !surfaces.contains(surface) {
+        return;
+    }
+
+    // a tick a frame, swapping in the past map tile by tile so only those get redrawn
+    if let Some(viewing) = timeline.viewing.as_mut().filter(|viewing| viewing.watching) {
+        let history = surfaces.history(surface);
+        match history.and_then(|history| Some((history.delta(viewing.tick)?, history.range()?))) {
+            Some((delta, (_, latest))) if viewing.tick + 1 < latest => {
+                for (pos, new) in delta {
+                    let old = std::mem::replace(viewing.map.get_mut_untracked(*pos), new.clone());
+                    tile_changes.send(SurfaceTileChanged {
+                        surface,
+                        change: TileChanged {
+                            pos: *pos,
+                            old,
+                            new: new.clone(),
+                        },
+                    });
+                }
+                viewing.tick += 1;
+            }
+            _ => {
+                timeline.viewing = None;
+                selected.set_changed();
+            }
+        }
+    }
+
+    let mut scrub_to = None;
+    let mut rewind_to = None;
+    egui::Window::new("History").show(egui_ctx.ctx_mut(), |ui| {
+        let Some(history) = surfaces.history(surface) else {
+            if ui.button("Record history").clicked() {
+                surfaces.set_history(surface, Some(HistorySettings::default()));
+            }
+            return;
+        };
+        let settings = history.settings();
+        ui.label(format!(
+            "keyframe every {} ticks, {} of {} KiB",
+            settings.keyframe_every,
+            history.size() / 1024,
+            settings.memory_budget / 1024
+        ));
+        let Some((oldest, latest)) = history.range() else {
+            ui.label("nothing recorded yet");
+            return;
+        };
+
+        let viewed = timeline.viewing.as_ref().map(|viewing| viewing.tick);
+        let mut tick = viewed.unwrap_or(latest);
+        if (ui.add(egui::Slider::new(&mut tick, oldest..=latest).text("tick"))).changed() {
+            scrub_to = Some(tick);
+        }
+
+        ui.horizontal(|ui| match &mut timeline.viewing {
+            Some(viewing) => {
+                let label = match viewing.watching {
+                    true => "Pause",
+                    false => "Watch",
+                };
+                if ui.button(label).clicked() {
+                    viewing.watching = !viewing.watching;
+                }
+                if ui.button("Resume from here").clicked() {
+                    rewind_to = Some(viewing.tick);
+                }
+                if ui.button("Back to live").clicked() {
+                    scrub_to = Some(latest);
+                }
+            }
+            None => {
+                if ui.button("Stop recording").clicked() {
+                    surfaces.set_history(surface, None);
+                }
+            }
+        });
+    });
+
+    if let Some(tick) = scrub_to {
+        let history = surfaces.history(surface);
+        let latest = history.and_then(History::range).map(|(_, latest)| latest);
+        timeline.viewing = match Some(tick) == latest {
+            true => None,
+            false => history
+                .and_then(|history| history.map_at(tick))
+                .map(|map| ViewedTick {
+                    surface,
+                    tick,
+                    map,
+                    watching: false,
+                }),
+        };
+        selected.set_changed();
+    }
+    if let Some(tick) = rewind_to {
+        if let Err(err) = surfaces.rewind_to(surface, tick) {
+            error!("couldn't rewind surface {surface} to tick {tick}: {err}");
+        }
+        timeline.viewing = None;
+        selected.set_changed();
+    }
+}
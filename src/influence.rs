@@ -0,0 +1,186 @@
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hexmap::{HexMap, HexPos},
+    schedule::{SurfaceStage, SurfaceSystem},
+    simulation::{MyTileData, TileKind},
+    surfaces::Surfaces,
+};
+
+/// Danger, mostly from fire
+pub struct Threat;
+/// Food and water worth moving towards, settlers found colonies where it's highest
+pub struct ResourceRichness;
+/// Who holds the land, kept up to date by the settlements systems
+pub struct Territory;
+
+/// How quickly a source's influence drops off with distance
+#[derive(Debug, Copy, Clone)]
+pub enum Falloff {
+    /// Reaches zero just past `max_steps`
+    Linear,
+    /// Multiplied by this much every step
+    Exponential(f32),
+}
+
+/// A layer of `f32` values over the map. `L` is a marker type so each layer
+/// can be its own surface resource, `InfluenceMap<Threat>`, `InfluenceMap<Territory>` etc.
+// Not Inspectable because HexMap isn't
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InfluenceMap<L> {
+    values: HexMap<f32>,
+    _layer: PhantomData<fn() -> L>,
+}
+
+impl<L> Clone for InfluenceMap<L> {
+    fn clone(&self) -> Self {
+        Self {
+            values: self.values.clone(),
+            _layer: PhantomData,
+        }
+    }
+}
+
+impl<L> InfluenceMap<L> {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            values: HexMap::new(width, height, vec![0.0; width * height]),
+            _layer: PhantomData,
+        }
+    }
+
+    pub fn get(&self, pos: HexPos) -> f32 {
+        *self.values.get(self.values.wrap(pos))
+    }
+
+    pub fn set(&mut self, pos: HexPos, value: f32) {
+        let pos = self.values.wrap(pos);
+        *self.values.get_mut_untracked(pos) = value;
+    }
+
+    pub fn values(&self) -> &HexMap<f32> {
+        &self.values
+    }
+
+    pub fn clear(&mut self) {
+        for pos in self.values.positions() {
+            *self.values.get_mut_untracked(pos) = 0.0;
+        }
+    }
+
+    /// Adds `strength` at each source, spreading out up to `max_steps` hexes and fading with `falloff`
+    pub fn propagate(
+        &mut self,
+        sources: impl IntoIterator<Item = (HexPos, f32)>,
+        falloff: Falloff,
+        max_steps: u32,
+    ) {
+        for (source, strength) in sources {
+            for (pos, distance) in self.values.within(source, max_steps) {
+                let scale = match falloff {
+                    Falloff::Linear => 1.0 - distance as f32 / (max_steps + 1) as f32,
+                    Falloff::Exponential(factor) => factor.powi(distance as i32),
+                };
+                *self.values.get_mut_untracked(pos) += strength * scale;
+            }
+        }
+    }
+
+    /// Multiplies every value by `factor`, call every tick so old influence fades away
+    pub fn decay(&mut self, factor: f32) {
+        self.zip_with(|value, _| value * factor, None::<&Self>);
+    }
+
+    pub fn add<M>(&mut self, other: &InfluenceMap<M>) {
+        self.zip_with(|a, b| a + b, Some(other));
+    }
+
+    pub fn subtract<M>(&mut self, other: &InfluenceMap<M>) {
+        self.zip_with(|a, b| a - b, Some(other));
+    }
+
+    pub fn max<M>(&mut self, other: &InfluenceMap<M>) {
+        self.zip_with(f32::max, Some(other));
+    }
+
+    fn zip_with<M>(&mut self, f: impl Fn(f32, f32) -> f32, other: Option<&InfluenceMap<M>>) {
+        if let Some(other) = other {
+            assert_eq!(self.values.width(), other.values.width());
+            assert_eq!(self.values.height(), other.values.height());
+        }
+        for pos in self.values.positions() {
+            let b = other.map_or(0.0, |other| *other.values.get(pos));
+            let value = self.values.get_mut_untracked(pos);
+            *value = f(*value, b);
+        }
+    }
+
+    /// The hex within `steps` of `from` with the lowest value, closer hexes win ties
+    pub fn lowest_within(&self, from: HexPos, steps: u32) -> HexPos {
+        self.values
+            .within(from, steps)
+            .min_by(|&(a, _), &(b, _)| self.get(a).total_cmp(&self.get(b)))
+            .unwrap()
+            .0
+    }
+
+    /// The hex within `steps` of `from` with the highest value, closer hexes win ties
+    pub fn highest_within(&self, from: HexPos, steps: u32) -> HexPos {
+        self.values
+            .within(from, steps)
+            .min_by(|&(a, _), &(b, _)| self.get(b).total_cmp(&self.get(a)))
+            .unwrap()
+            .0
+    }
+}
+
+impl InfluenceMap<Threat> {
+    /// e.g. "the safest hex within 5 steps"
+    pub fn safest_within(&self, from: HexPos, steps: u32) -> HexPos {
+        self.lowest_within(from, steps)
+    }
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| {
+            let map = world.resource::<HexMap<MyTileData>>();
+            let (width, height) = (map.width(), map.height());
+            world.insert_resource(InfluenceMap::<Threat>::new(width, height));
+            world.insert_resource(InfluenceMap::<ResourceRichness>::new(width, height));
+            world.insert_resource(InfluenceMap::<Territory>::new(width, height));
+        })
+        .push_state_resource::<InfluenceMap<Threat>>()
+        .push_state_resource::<InfluenceMap<ResourceRichness>>()
+        .push_state_resource::<InfluenceMap<Territory>>()
+        .push_system(SurfaceSystem::new(update_threat).in_stage(SurfaceStage::PreSim))
+        .push_system(SurfaceSystem::new(update_resource_richness).in_stage(SurfaceStage::PreSim));
+}
+
+fn update_threat(map: Res<HexMap<MyTileData>>, mut threat: ResMut<InfluenceMap<Threat>>) {
+    threat.decay(0.9);
+    let fires = map
+        .positions()
+        .filter(|&pos| map.get(pos).fire.is_burning())
+        .map(|pos| (pos, 0.1));
+    threat.propagate(fires, Falloff::Exponential(0.5), 4);
+}
+
+fn update_resource_richness(
+    map: Res<HexMap<MyTileData>>,
+    mut richness: ResMut<InfluenceMap<ResourceRichness>>,
+) {
+    richness.clear();
+    let resources = map.positions().filter_map(|pos| {
+        let tile = map.get(pos);
+        let value = match tile.kind {
+            TileKind::Water => 0.5,
+            TileKind::Rock => tile.vegetation.density,
+        };
+        (value > 0.0).then_some((pos, value))
+    });
+    richness.propagate(resources, Falloff::Linear, 2);
+}
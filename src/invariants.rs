@@ -0,0 +1,169 @@
+use std::{fmt::Write as _, path::Path, sync::Arc};
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{bevy_egui::EguiContext, egui};
+use iyes_loopless::prelude::*;
+
+use crate::{
+    hexmap::{HexMap, HexPos},
+    simulation::{MyTileData, TileKind},
+    surfaces::{SurfaceId, Surfaces},
+    AppState,
+};
+
+/// What went wrong and where
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub message: String,
+    pub tiles: Vec<HexPos>,
+}
+
+/// Takes `&mut World` so it can run queries, it shouldn't change anything
+pub type InvariantCheck = Arc<dyn Fn(&mut World) -> Result<(), Violation> + Send + Sync>;
+
+pub struct Invariant {
+    pub name: String,
+    pub check: InvariantCheck,
+}
+
+/// Checks run against a surface world after every step while invariant checking is on,
+/// see `Surfaces::set_invariant_checks`
+// Not Inspectable because of the closures
+#[derive(Default)]
+pub struct Invariants(pub Vec<Invariant>);
+
+/// Every invariant that failed on a surface after one of its steps
+#[derive(Debug, Clone)]
+pub struct InvariantReport {
+    pub surface: SurfaceId,
+    pub tick: u64,
+    pub violations: Vec<(String, Violation)>,
+}
+
+/// Runs every invariant registered on `world`, `None` if they all passed
+pub fn check_surface(surface: SurfaceId, tick: u64, world: &mut World) -> Option<InvariantReport> {
+    let invariants = world
+        .get_resource::<Invariants>()?
+        .0
+        .iter()
+        .map(|invariant| (invariant.name.clone(), invariant.check.clone()))
+        .collect::<Vec<_>>();
+    let violations = invariants
+        .into_iter()
+        .filter_map(|(name, check)| check(world).err().map(|violation| (name, violation)))
+        .collect::<Vec<_>>();
+    match violations.is_empty() {
+        true => None,
+        false => Some(InvariantReport {
+            surface,
+            tick,
+            violations,
+        }),
+    }
+}
+
+/// Logs the report, and writes it along with every tile and positioned entity to
+/// `dump_dir/surface<n>_tick<t>.txt` if there's a `dump_dir`
+pub fn report(report: &InvariantReport, world: &mut World, dump_dir: Option<&Path>) {
+    for (name, violation) in report.violations.iter() {
+        error!(
+            "surface {} tick {}: invariant \"{name}\" failed: {} at {:?}",
+            report.surface, report.tick, violation.message, violation.tiles
+        );
+    }
+
+    let Some(dump_dir) = dump_dir else {
+        return;
+    };
+    let mut dump = format!("{report:#?}\n\n");
+    let map = world.resource::<HexMap<MyTileData>>();
+    for pos in map.positions() {
+        writeln!(dump, "({}, {}) {:?}", pos.q, pos.r, map.get(pos)).unwrap();
+    }
+    dump.push('\n');
+    let mut entities = world.query::<(Entity, &HexPos)>();
+    for (entity, pos) in entities.iter(world) {
+        writeln!(dump, "{entity:?} at ({}, {})", pos.q, pos.r).unwrap();
+    }
+
+    let path = dump_dir.join(format!("surface{}_tick{}.txt", report.surface, report.tick));
+    let written = std::fs::create_dir_all(dump_dir).and_then(|()| std::fs::write(&path, dump));
+    match written {
+        Ok(()) => info!("dumped surface state to {}", path.display()),
+        Err(err) => error!("couldn't dump surface state to {}: {err}", path.display()),
+    }
+}
+
+pub fn init_app(app: &mut App) {
+    app.add_system(invariant_failure_window.run_in_state(AppState::Playing));
+}
+
+pub fn add_invariants(surfaces: &mut Surfaces) {
+    surfaces
+        .push_invariant("entities stand on the map", |world| {
+            let map = world.resource::<HexMap<MyTileData>>();
+            let (width, height) = (map.width() as i32, map.height() as i32);
+            let tiles = world
+                .query::<&HexPos>()
+                .iter(world)
+                .filter(|pos| !(0..width).contains(&pos.q) || !(0..height).contains(&pos.r))
+                .copied()
+                .collect::<Vec<_>>();
+            violation_if_any("entities outside the map", tiles)
+        })
+        .push_invariant("vegetation density within 0..=1", |world| {
+            let map = world.resource::<HexMap<MyTileData>>();
+            tiles_where(map, "bad vegetation density", |tile| {
+                !(0.0..=1.0).contains(&tile.vegetation.density)
+            })
+        })
+        .push_invariant("lava depth finite and not negative", |world| {
+            let map = world.resource::<HexMap<MyTileData>>();
+            tiles_where(map, "bad lava depth", |tile| {
+                !tile.lava.depth.is_finite() || tile.lava.depth < 0.0
+            })
+        })
+        .push_invariant("nothing burns underwater", |world| {
+            let map = world.resource::<HexMap<MyTileData>>();
+            tiles_where(map, "water tiles on fire", |tile| {
+                tile.kind == TileKind::Water && tile.fire.is_burning()
+            })
+        });
+}
+
+fn tiles_where(
+    map: &HexMap<MyTileData>,
+    message: &str,
+    pred: impl Fn(&MyTileData) -> bool,
+) -> Result<(), Violation> {
+    let tiles = map.positions().filter(|&pos| pred(map.get(pos))).collect();
+    violation_if_any(message, tiles)
+}
+
+fn violation_if_any(message: &str, tiles: Vec<HexPos>) -> Result<(), Violation> {
+    match tiles.is_empty() {
+        true => Ok(()),
+        false => Err(Violation {
+            message: message.to_owned(),
+            tiles,
+        }),
+    }
+}
+
+/// Shows what failed while the simulation is paused on a failed invariant
+fn invariant_failure_window(mut egui_ctx: ResMut<EguiContext>, mut surfaces: ResMut<Surfaces>) {
+    let Some(report) = surfaces.invariant_failure().cloned() else {
+        return;
+    };
+
+    egui::Window::new("Invariant failed").show(egui_ctx.ctx_mut(), |ui| {
+        ui.label(format!("surface {} tick {}", report.surface, report.tick));
+        for (name, violation) in report.violations.iter() {
+            ui.label(format!("{name}: {}", violation.message));
+            ui.monospace(format!("{:?}", violation.tiles));
+        }
+        if ui.button("Resume").clicked() {
+            surfaces.resume();
+        }
+    });
+}
@@ -5,11 +5,29 @@ use bevy::{
 use bevy_inspector_egui::{Inspectable, WorldInspectorPlugin};
 use iyes_loopless::prelude::*;
 
+pub mod agents;
+pub mod ai;
+pub mod calendar;
 pub mod draw;
+pub mod economy;
+pub mod flowfield;
+pub mod geology;
 pub mod hexmap;
+pub mod history;
+pub mod influence;
+pub mod invariants;
 pub mod loading;
+pub mod rng;
+pub mod save;
+pub mod schedule;
+pub mod sealevel;
+pub mod settlements;
 pub mod simulation;
+pub mod snapshot;
+pub mod stats;
 pub mod surfaces;
+pub mod vegetation;
+pub mod wildfire;
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Inspectable)]
 pub enum AppState {
@@ -22,12 +40,21 @@ fn main() {
     app.add_loopless_state(AppState::Loading)
         .add_plugins(DefaultPlugins)
         // Adds frame time diagnostics
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(FrameTimeDiagnosticsPlugin)
         // Adds a system that prints diagnostics to the console
         .add_plugin(LogDiagnosticsPlugin::default())
         .add_plugin(WorldInspectorPlugin::new());
     draw::init_app(&mut app);
     simulation::init_app(&mut app);
     loading::init_app(&mut app);
+    wildfire::init_app(&mut app);
+    geology::init_app(&mut app);
+    ai::init_app(&mut app);
+    economy::init_app(&mut app);
+    calendar::init_app(&mut app);
+    invariants::init_app(&mut app);
+    stats::init_app(&mut app);
+    save::init_app(&mut app);
+    history::init_app(&mut app);
     app.run();
 }
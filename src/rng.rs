@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// A small seedable PRNG (SplitMix64). We don't pull in `rand` because the exact sequence has to
+/// stay stable across dependency bumps, otherwise old replays/bug reports stop reproducing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn from_seed(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Creates a new generator from this one's seed mixed with `salt`, without advancing `self`
+    pub fn fork(&self, salt: u64) -> Self {
+        Self::from_seed(mix(self.state ^ mix(salt)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mix(self.state)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in `[0, bound)`, `bound` must be non-zero
+    pub fn below(&mut self, bound: u32) -> u32 {
+        assert!(bound != 0);
+        ((self.next_u32() as u64 * bound as u64) >> 32) as u32
+    }
+
+    /// Returns `true` with probability `p`
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.next_f32() < p
+    }
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// FNV-1a, used instead of `std`'s hasher because that one is randomly keyed per process
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+/// Per surface RNG resource, inserted by `Surfaces::new_surface`.
+///
+/// Systems should grab their own stream with `stream("my_system")` rather than sharing one
+/// generator, that way adding or reordering systems doesn't change anyone else's sequence.
+// Not Inspectable because of HashMap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurfaceRng {
+    root: Rng,
+    streams: HashMap<String, Rng>,
+}
+
+impl SurfaceRng {
+    /// `surface_number` is how many surfaces were created before this one
+    pub fn new(master_seed: u64, surface_number: u64) -> Self {
+        Self {
+            root: Rng::from_seed(master_seed).fork(surface_number),
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Gets the named sub-stream, creating it on first use. The starting state only depends on the
+    /// surface seed and `name` so it doesn't matter when/in what order streams get created.
+    pub fn stream(&mut self, name: &str) -> &mut Rng {
+        let root = &self.root;
+        self.streams
+            .entry(name.to_owned())
+            .or_insert_with(|| root.fork(hash_name(name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(rng: &mut Rng, n: usize) -> Vec<u64> {
+        (0..n).map(|_| rng.next_u64()).collect()
+    }
+
+    #[test]
+    fn streams_dont_depend_on_each_other() {
+        let mut alone = SurfaceRng::new(7, 0);
+        let expected = take(alone.stream("a"), 20);
+
+        let mut shared = SurfaceRng::new(7, 0);
+        take(shared.stream("b"), 100);
+        let mut a = take(shared.stream("a"), 10);
+        take(shared.stream("b"), 100);
+        a.extend(take(shared.stream("a"), 10));
+        assert_eq!(a, expected);
+    }
+
+    #[test]
+    fn same_seed_and_name_give_the_same_sequence() {
+        let sequence = |master_seed, surface_number, name| {
+            take(
+                SurfaceRng::new(master_seed, surface_number).stream(name),
+                20,
+            )
+        };
+        assert_eq!(sequence(7, 0, "a"), sequence(7, 0, "a"));
+        assert_ne!(sequence(7, 0, "a"), sequence(7, 0, "b"));
+        assert_ne!(sequence(7, 0, "a"), sequence(7, 1, "a"));
+        assert_ne!(sequence(7, 0, "a"), sequence(8, 0, "a"));
+    }
+}
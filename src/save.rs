@@ -0,0 +1,155 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    draw::Action,
+    snapshot::{SavedEntity, SavedValues},
+    surfaces::{SelectedSurface, SurfaceId, Surfaces, TickRate},
+    AppState,
+};
+
+/// Bumped whenever the layout of `SaveFile` changes
+pub const SAVE_VERSION: u32 = 3;
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+    /// Only systems pushed onto every surface or onto tagged surfaces can be saved, ones pushed onto
+    /// a single surface with `Surfaces::push_surface_system` only exist in the code that pushed them
+    SurfaceSystem(SurfaceId),
+    /// The save doesn't fit the systems and state types this build pushes onto `Surfaces`
+    Mismatch(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Ron(err) => write!(f, "{err}"),
+            Self::SurfaceSystem(id) => write!(f, "surface {id} has a system of its own"),
+            Self::Mismatch(message) => write!(f, "save doesn't match this build: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ron::Error> for SaveError {
+    fn from(err: ron::Error) -> Self {
+        Self::Ron(err)
+    }
+}
+
+/// What's in a save, see `Surfaces::save`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SurfaceManifest {
+    pub id: SurfaceId,
+    pub tick: u64,
+    pub tags: Vec<String>,
+    pub rate: TickRate,
+    pub dormant: bool,
+    /// Ticks owed towards the surface's next step, see `TickRate`
+    pub progress: u32,
+    /// Names of the surface's systems in the order they run
+    pub systems: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSurface {
+    /// Indices of the surface's systems into `Surfaces`' pushed systems, in the order they were added
+    pub systems: Vec<usize>,
+    pub resources: SavedValues,
+    pub entities: Vec<SavedEntity>,
+    /// `Entity::to_bits` of the ids free to reuse, see `snapshot::sort_free_entities`
+    pub free_entities: Vec<u64>,
+}
+
+/// One save holds every surface. `slot_generations` and `free_slots` are there so surfaces keep
+/// their `SurfaceId`s and surfaces created after loading get the same ids they would have.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveFile {
+    pub version: u32,
+    pub master_seed: u64,
+    pub created: u64,
+    pub manifest: Vec<SurfaceManifest>,
+    pub slot_generations: Vec<u32>,
+    pub free_slots: Vec<u32>,
+    /// In the same order as `manifest`
+    pub surfaces: Vec<SavedSurface>,
+}
+
+impl SaveFile {
+    pub fn write(&self, path: &Path) -> Result<(), SaveError> {
+        let ron = ron::ser::to_string_pretty(self, PrettyConfig::default())?;
+        std::fs::write(path, ron)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, SaveError> {
+        let save = ron::from_str::<Self>(&std::fs::read_to_string(path)?)?;
+        if save.version != SAVE_VERSION {
+            return Err(SaveError::Mismatch(format!(
+                "save is version {}, expected {SAVE_VERSION}",
+                save.version
+            )));
+        }
+        if save.manifest.len() != save.surfaces.len() {
+            return Err(SaveError::Mismatch(
+                "manifest and surfaces have different lengths".to_owned(),
+            ));
+        }
+        Ok(save)
+    }
+}
+
+/// `HEXY_SAVE` or `hexy_save.ron`
+fn save_path() -> PathBuf {
+    std::env::var("HEXY_SAVE")
+        .unwrap_or_else(|_| "hexy_save.ron".to_owned())
+        .into()
+}
+
+pub fn init_app(app: &mut App) {
+    app.add_system(save_and_load.run_in_state(AppState::Playing));
+}
+
+/// F5 saves every surface, F9 loads them back
+fn save_and_load(
+    actions: Query<&ActionState<Action>, With<Camera>>,
+    mut surfaces: ResMut<Surfaces>,
+    mut selected: ResMut<SelectedSurface>,
+) {
+    let actions = actions.single();
+    let path = save_path();
+    if actions.just_pressed(Action::Save) {
+        match surfaces.save(&path) {
+            Ok(()) => info!("saved to {}", path.display()),
+            Err(err) => error!("couldn't save to {}: {err}", path.display()),
+        }
+    }
+    if actions.just_pressed(Action::Load) {
+        match surfaces.load(&path) {
+            Ok(()) => {
+                info!("loaded {}", path.display());
+                // every tile may have changed, this gets the selected surface redrawn
+                selected.set_changed();
+            }
+            Err(err) => error!("couldn't load {}: {err}", path.display()),
+        }
+    }
+}
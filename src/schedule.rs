@@ -0,0 +1,536 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashSet},
+    sync::Arc,
+};
+
+use bevy::{
+    ecs::{schedule::SystemLabelId, system::Resource},
+    prelude::*,
+};
+
+use crate::surfaces::SurfaceTick;
+
+/// The stages a surface's schedule is made of, run in this order every tick
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SurfaceStage {
+    /// Applying queued events and working out things the simulation reads, e.g. the sea level
+    PreSim,
+    Sim,
+    /// Bookkeeping for what happened this tick
+    PostSim,
+}
+
+impl SurfaceStage {
+    pub const ALL: [Self; 3] = [Self::PreSim, Self::Sim, Self::PostSim];
+}
+
+/// Run condition for `SurfaceSystem::run_if`, true on every `n`th tick starting from tick `0`
+pub fn every_n_ticks(n: u64) -> impl Fn(Res<SurfaceTick>) -> bool + Clone + Send + Sync {
+    move |tick: Res<SurfaceTick>| tick.0.is_multiple_of(n.max(1))
+}
+
+/// Run condition for `SurfaceSystem::run_if`, true when `T` has been added or changed since the
+/// condition last ran
+pub fn resource_changed<T: Resource>(resource: Option<Res<T>>) -> bool {
+    resource.is_some_and(|resource| resource.is_changed())
+}
+
+type BoxedSystem<In = (), Out = ()> = Box<dyn System<In = In, Out = Out> + Send + Sync>;
+type SystemCtor<Out = ()> = Arc<dyn Fn() -> BoxedSystem<(), Out> + Send + Sync>;
+
+/// A system along with where it goes in a surface's schedule. Holds a way of making the system
+/// rather than the system itself so the same `SurfaceSystem` can be added to many surfaces.
+#[derive(Clone)]
+pub struct SurfaceSystem {
+    system: SystemCtor,
+    stage: SurfaceStage,
+    labels: Vec<SystemLabelId>,
+    before: Vec<SystemLabelId>,
+    after: Vec<SystemLabelId>,
+    conditions: Vec<SystemCtor<bool>>,
+    when_dormant: bool,
+}
+
+impl SurfaceSystem {
+    /// In the `Sim` stage with no labels, ordering or run conditions
+    pub fn new<Params>(
+        system: impl IntoSystem<(), (), Params> + Clone + Send + Sync + 'static,
+    ) -> Self {
+        Self::from_ctor(Arc::new(move || {
+            Box::new(IntoSystem::into_system(system.clone()))
+        }))
+    }
+
+    /// Feeds the output of `first` into `second` as its `In` parameter
+    pub fn piped<Out: 'static, FirstParams, SecondParams>(
+        first: impl IntoSystem<(), Out, FirstParams> + Clone + Send + Sync + 'static,
+        second: impl IntoSystem<Out, (), SecondParams> + Clone + Send + Sync + 'static,
+    ) -> Self {
+        Self::from_ctor(Arc::new(move || {
+            Box::new(IntoSystem::into_system(first.clone()).chain(second.clone()))
+        }))
+    }
+
+    /// Whether `self` and `other` are clones of the same `SurfaceSystem`
+    pub fn is_same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.system, &other.system)
+    }
+
+    fn from_ctor(system: SystemCtor) -> Self {
+        Self {
+            system,
+            stage: SurfaceStage::Sim,
+            labels: vec![],
+            before: vec![],
+            after: vec![],
+            conditions: vec![],
+            when_dormant: false,
+        }
+    }
+
+    pub fn in_stage(mut self, stage: SurfaceStage) -> Self {
+        self.stage = stage;
+        self
+    }
+
+    pub fn label(mut self, label: impl SystemLabel) -> Self {
+        self.labels.push(label.as_label());
+        self
+    }
+
+    /// Runs before every system in the same stage with `label`, adding it to a schedule panics if
+    /// a system in another stage has `label`
+    pub fn before(mut self, label: impl SystemLabel) -> Self {
+        self.before.push(label.as_label());
+        self
+    }
+
+    /// Runs after every system in the same stage with `label`, adding it to a schedule panics if a
+    /// system in another stage has `label`
+    pub fn after(mut self, label: impl SystemLabel) -> Self {
+        self.after.push(label.as_label());
+        self
+    }
+
+    /// Keeps running while the surface is dormant, dormant surfaces only run the systems that
+    /// matter at low detail. See `Surfaces::set_dormant`.
+    pub fn when_dormant(mut self) -> Self {
+        self.when_dormant = true;
+        self
+    }
+
+    /// Skips the system on ticks where `condition` returns false, every condition has to pass
+    pub fn run_if<Params>(
+        mut self,
+        condition: impl IntoSystem<(), bool, Params> + Clone + Send + Sync + 'static,
+    ) -> Self {
+        self.conditions.push(Arc::new(move || {
+            Box::new(IntoSystem::into_system(condition.clone()))
+        }));
+        self
+    }
+}
+
+pub trait IntoSurfaceSystem<Params> {
+    fn into_surface_system(self) -> SurfaceSystem;
+}
+
+impl IntoSurfaceSystem<()> for SurfaceSystem {
+    fn into_surface_system(self) -> SurfaceSystem {
+        self
+    }
+}
+
+impl<Params, S> IntoSurfaceSystem<(Params,)> for S
+where
+    S: IntoSystem<(), (), Params> + Clone + Send + Sync + 'static,
+{
+    fn into_surface_system(self) -> SurfaceSystem {
+        SurfaceSystem::new(self)
+    }
+}
+
+struct ScheduledSystem {
+    system: BoxedSystem,
+    conditions: Vec<BoxedSystem<(), bool>>,
+    labels: Vec<SystemLabelId>,
+    before: Vec<SystemLabelId>,
+    after: Vec<SystemLabelId>,
+    when_dormant: bool,
+}
+
+impl ScheduledSystem {
+    fn should_run(&mut self, world: &mut World) -> bool {
+        self.conditions.iter_mut().all(|condition| {
+            condition.update_archetype_component_access(world);
+            condition.run((), world)
+        })
+    }
+
+    /// Whether `self` has to run before `other`
+    fn runs_before(&self, other: &Self) -> bool {
+        self.before.iter().any(|label| other.labels.contains(label))
+            || other.after.iter().any(|label| self.labels.contains(label))
+    }
+}
+
+#[derive(Default)]
+struct Stage {
+    systems: Vec<ScheduledSystem>,
+    /// Indices into `systems` in the order they run
+    order: Vec<usize>,
+}
+
+impl Stage {
+    /// Sorts the systems by their `before`/`after` labels, systems that aren't ordered relative to
+    /// each other run in the order they were added so the simulation stays deterministic. Returns
+    /// the names of the systems in a cycle if there is one.
+    fn run_order(&self) -> Result<Vec<usize>, Vec<Cow<'static, str>>> {
+        let count = self.systems.len();
+        let mut dependencies = vec![HashSet::new(); count];
+        for (a, system_a) in self.systems.iter().enumerate() {
+            for (b, system_b) in self.systems.iter().enumerate() {
+                if a != b && system_a.runs_before(system_b) {
+                    dependencies[b].insert(a);
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(count);
+        let mut ready = (0..count)
+            .filter(|&system| dependencies[system].is_empty())
+            .collect::<BTreeSet<_>>();
+        while let Some(system) = ready.pop_first() {
+            order.push(system);
+            for (other, other_dependencies) in dependencies.iter_mut().enumerate() {
+                if other_dependencies.remove(&system) && other_dependencies.is_empty() {
+                    ready.insert(other);
+                }
+            }
+        }
+
+        if order.len() != count {
+            return Err((0..count)
+                .filter(|system| !order.contains(system))
+                .map(|system| self.systems[system].system.name())
+                .collect());
+        }
+        Ok(order)
+    }
+
+    /// A `before`/`after` label `system` has that a system in this stage carries, or the other way
+    /// around
+    fn shared_ordering_label(&self, system: &SurfaceSystem) -> Option<SystemLabelId> {
+        self.systems.iter().find_map(|other| {
+            (system.before.iter().chain(system.after.iter()))
+                .find(|label| other.labels.contains(label))
+                .or_else(|| {
+                    (system.labels.iter())
+                        .find(|label| other.before.contains(label) || other.after.contains(label))
+                })
+                .copied()
+        })
+    }
+}
+
+/// Runs the systems in each `SurfaceStage` one after another with no parallelism,
+/// flushing commands after each system runs
+// Not Inspectable because...Rust magic?
+#[derive(Default)]
+pub struct SurfaceSchedule {
+    stages: [Stage; 3],
+    /// Every system in the order it was added, for `fork`
+    added: Vec<SurfaceSystem>,
+}
+
+impl SurfaceSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// this fn will handle initializing `system`. Panics without adding it if that would make the
+    /// `before`/`after` order cyclic, or if it's ordered against a system in another stage.
+    pub fn add_system(&mut self, system: &SurfaceSystem, world: &mut World) -> &mut Self {
+        let mut scheduled = ScheduledSystem {
+            system: (system.system)(),
+            conditions: system.conditions.iter().map(|ctor| ctor()).collect(),
+            labels: system.labels.clone(),
+            before: system.before.clone(),
+            after: system.after.clone(),
+            when_dormant: system.when_dormant,
+        };
+        scheduled.system.initialize(world);
+        for condition in scheduled.conditions.iter_mut() {
+            condition.initialize(world);
+        }
+
+        for (stage, other) in SurfaceStage::ALL.into_iter().zip(self.stages.iter()) {
+            if stage == system.stage {
+                continue;
+            }
+            if let Some(label) = other.shared_ordering_label(system) {
+                panic!(
+                    "{} is ordered against {label:?} in {stage:?} but is in {:?}, \
+                    systems can only be ordered within a stage",
+                    scheduled.system.name(),
+                    system.stage
+                );
+            }
+        }
+
+        let stage = &mut self.stages[system.stage as usize];
+        stage.systems.push(scheduled);
+        match stage.run_order() {
+            Ok(order) => stage.order = order,
+            Err(cycle) => {
+                // leave the schedule as it was
+                stage.systems.pop();
+                panic!("surface systems have a cyclic before/after order: {cycle:?}");
+            }
+        }
+        self.added.push(system.clone());
+
+        self
+    }
+
+    /// Every system in the order it was added
+    pub fn added(&self) -> impl Iterator<Item = &SurfaceSystem> {
+        self.added.iter()
+    }
+
+    /// A schedule with the same systems in the same order, initialized on `world`. The systems
+    /// start over with fresh `Local`s and change ticks.
+    pub fn fork(&self, world: &mut World) -> Self {
+        let mut schedule = Self::new();
+        for system in self.added.iter() {
+            schedule.add_system(system, world);
+        }
+        schedule
+    }
+
+    /// Names of every system in the order they run
+    pub fn system_names(&self) -> impl Iterator<Item = (SurfaceStage, Cow<'static, str>)> + '_ {
+        SurfaceStage::ALL
+            .into_iter()
+            .zip(self.stages.iter())
+            .flat_map(|(label, stage)| {
+                (stage.order.iter())
+                    .map(move |&system| (label, stage.systems[system].system.name()))
+            })
+    }
+
+    /// Keeps every system's last change tick from getting so old it wraps around,
+    /// see `World::check_change_ticks`
+    pub fn check_change_ticks(&mut self, change_tick: u32) {
+        for stage in self.stages.iter_mut() {
+            for scheduled in stage.systems.iter_mut() {
+                scheduled.system.check_change_tick(change_tick);
+                for condition in scheduled.conditions.iter_mut() {
+                    condition.check_change_tick(change_tick);
+                }
+            }
+        }
+    }
+
+    /// `dormant` skips every system not added with `SurfaceSystem::when_dormant`
+    pub fn run_once(&mut self, world: &mut World, dormant: bool) {
+        for stage in self.stages.iter_mut() {
+            for &system in stage.order.iter() {
+                let scheduled = &mut stage.systems[system];
+                if dormant && !scheduled.when_dormant {
+                    continue;
+                }
+                if !scheduled.should_run(world) {
+                    continue;
+                }
+                scheduled.system.update_archetype_component_access(world);
+                scheduled.system.run((), world);
+                scheduled.system.apply_buffers(world);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+
+    /// What the test systems did, in order
+    #[derive(Default)]
+    struct Ran(Vec<String>);
+
+    #[derive(SystemLabel, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    enum TestLabel {
+        A,
+        B,
+    }
+
+    fn a(mut ran: ResMut<Ran>) {
+        ran.0.push("a".to_owned());
+    }
+
+    fn b(mut ran: ResMut<Ran>) {
+        ran.0.push("b".to_owned());
+    }
+
+    fn c(mut ran: ResMut<Ran>) {
+        ran.0.push("c".to_owned());
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Ran>();
+        world.init_resource::<SurfaceTick>();
+        world
+    }
+
+    fn run(schedule: &mut SurfaceSchedule, world: &mut World) -> Vec<String> {
+        schedule.run_once(world, false);
+        std::mem::take(&mut world.resource_mut::<Ran>().0)
+    }
+
+    fn schedule(world: &mut World, systems: &[SurfaceSystem]) -> SurfaceSchedule {
+        let mut schedule = SurfaceSchedule::new();
+        for system in systems {
+            schedule.add_system(system, world);
+        }
+        schedule
+    }
+
+    #[test]
+    fn labels_order_systems() {
+        let mut world = world();
+        let mut schedule = schedule(
+            &mut world,
+            &[
+                SurfaceSystem::new(c).after(TestLabel::B),
+                SurfaceSystem::new(b)
+                    .label(TestLabel::B)
+                    .after(TestLabel::A),
+                SurfaceSystem::new(a).label(TestLabel::A),
+            ],
+        );
+        assert_eq!(run(&mut schedule, &mut world), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn label_order_ignores_insertion_order() {
+        let systems = [
+            SurfaceSystem::new(a).before(TestLabel::B),
+            SurfaceSystem::new(b).label(TestLabel::B),
+            SurfaceSystem::new(c).after(TestLabel::B),
+        ];
+        let orders = [[0, 1, 2], [2, 1, 0], [1, 2, 0], [2, 0, 1]];
+        for order in orders {
+            let mut world = world();
+            let systems = order.map(|i| systems[i].clone());
+            let mut schedule = schedule(&mut world, &systems);
+            assert_eq!(run(&mut schedule, &mut world), ["a", "b", "c"], "{order:?}");
+        }
+    }
+
+    #[test]
+    fn unordered_systems_run_in_insertion_order() {
+        let mut world = world();
+        let systems = [
+            SurfaceSystem::new(c),
+            SurfaceSystem::new(a),
+            SurfaceSystem::new(b),
+        ];
+        let mut schedule = schedule(&mut world, &systems);
+        assert_eq!(run(&mut schedule, &mut world), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn cycles_panic_without_changing_the_schedule() {
+        let mut world = world();
+        let mut schedule = schedule(
+            &mut world,
+            &[
+                SurfaceSystem::new(a).label(TestLabel::A),
+                SurfaceSystem::new(b)
+                    .label(TestLabel::B)
+                    .after(TestLabel::A),
+            ],
+        );
+        let cyclic = SurfaceSystem::new(c)
+            .after(TestLabel::B)
+            .before(TestLabel::A);
+        let added = catch_unwind(AssertUnwindSafe(|| {
+            schedule.add_system(&cyclic, &mut world);
+        }));
+        assert!(added.is_err());
+        assert_eq!(schedule.added().count(), 2);
+        assert_eq!(schedule.system_names().count(), 2);
+        assert_eq!(run(&mut schedule, &mut world), ["a", "b"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "can only be ordered within a stage")]
+    fn ordering_across_stages_panics() {
+        let mut world = world();
+        schedule(
+            &mut world,
+            &[
+                SurfaceSystem::new(a)
+                    .label(TestLabel::A)
+                    .in_stage(SurfaceStage::PreSim),
+                SurfaceSystem::new(b).after(TestLabel::A),
+            ],
+        );
+    }
+
+    #[test]
+    fn every_n_ticks_runs_on_multiples() {
+        let mut world = world();
+        let mut schedule = schedule(
+            &mut world,
+            &[SurfaceSystem::new(a).run_if(every_n_ticks(3))],
+        );
+        let ran_on = (0..8)
+            .filter(|&tick| {
+                world.insert_resource(SurfaceTick(tick));
+                !run(&mut schedule, &mut world).is_empty()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ran_on, [0, 3, 6]);
+    }
+
+    #[test]
+    fn resource_changed_runs_after_changes() {
+        #[derive(Default)]
+        struct Watched(u32);
+
+        let mut world = world();
+        let mut schedule = schedule(
+            &mut world,
+            &[SurfaceSystem::new(a).run_if(resource_changed::<Watched>)],
+        );
+        assert!(run(&mut schedule, &mut world).is_empty());
+        world.init_resource::<Watched>();
+        assert_eq!(run(&mut schedule, &mut world), ["a"]);
+        assert!(run(&mut schedule, &mut world).is_empty());
+        world.resource_mut::<Watched>().0 += 1;
+        assert_eq!(run(&mut schedule, &mut world), ["a"]);
+        assert!(run(&mut schedule, &mut world).is_empty());
+    }
+
+    #[test]
+    fn piped_systems_pass_their_output_on() {
+        fn count(tick: Res<SurfaceTick>) -> u64 {
+            tick.0 * 2
+        }
+
+        fn record(In(count): In<u64>, mut ran: ResMut<Ran>) {
+            ran.0.push(count.to_string());
+        }
+
+        let mut world = world();
+        let mut schedule = schedule(&mut world, &[SurfaceSystem::piped(count, record)]);
+        world.insert_resource(SurfaceTick(21));
+        assert_eq!(run(&mut schedule, &mut world), ["42"]);
+    }
+}
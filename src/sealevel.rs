@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hexmap::{HexMap, HexPos},
+    schedule::{SurfaceStage, SurfaceSystem},
+    simulation::{MyTileData, TileKind},
+    surfaces::{SurfaceTick, Surfaces},
+    wildfire::Fire,
+};
+
+/// Where the sea floods in from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OceanSources {
+    /// Every tile on the edge of the map
+    MapEdges,
+    /// Only these tiles, e.g. for maps that are land all the way round
+    Tiles(Vec<HexPos>),
+}
+
+/// Tiles lower than `current` that are connected to the ocean are underwater
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeaLevel {
+    /// In the same units as `MyTileData::height`
+    pub base: f32,
+    /// How far above and below the base the tide goes
+    pub tide_amplitude: f32,
+    /// Ticks from one high tide to the next, 0 for no tide
+    pub tide_period: u32,
+    /// Added to the base every tick, e.g. for an ice age
+    pub trend_per_tick: f32,
+    pub sources: OceanSources,
+    /// Worked out from the above every tick
+    pub current: f32,
+}
+
+impl Default for SeaLevel {
+    fn default() -> Self {
+        Self {
+            base: 2.5,
+            tide_amplitude: 0.4,
+            tide_period: 600,
+            trend_per_tick: 0.0,
+            sources: OceanSources::MapEdges,
+            current: 2.5,
+        }
+    }
+}
+
+impl SeaLevel {
+    pub fn at_tick(&self, tick: u64) -> f32 {
+        let level = self.base + self.trend_per_tick * tick as f32;
+        if self.tide_period == 0 {
+            return level;
+        }
+        let tide_angle = std::f32::consts::TAU * (tick % self.tide_period as u64) as f32
+            / self.tide_period as f32;
+        level + self.tide_amplitude * tide_angle.sin()
+    }
+
+    pub fn is_below(&self, tile: &MyTileData) -> bool {
+        (tile.height as f32) < self.current
+    }
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| world.init_resource::<SeaLevel>())
+        .push_state_resource::<SeaLevel>()
+        .push_system(
+            SurfaceSystem::new(update_sea_level)
+                .in_stage(SurfaceStage::PreSim)
+                .when_dormant(),
+        )
+        .push_system(SurfaceSystem::new(flood_and_drain).when_dormant())
+        .push_metric("sea level", |sea: Res<SeaLevel>| sea.current);
+}
+
+fn update_sea_level(tick: Res<SurfaceTick>, mut sea: ResMut<SeaLevel>) {
+    sea.current = sea.at_tick(tick.0);
+}
+
+/// Floods every tile below the sea level that the ocean can reach, and drains water tiles that
+/// are now above it. Water below the sea level that the ocean can't reach (i.e. lakes) is left alone.
+fn flood_and_drain(mut map: ResMut<HexMap<MyTileData>>, sea: Res<SeaLevel>) {
+    let (width, height) = (map.width() as i32, map.height() as i32);
+    let sources = match &sea.sources {
+        OceanSources::MapEdges => map
+            .positions()
+            .filter(|pos| pos.q == 0 || pos.r == 0 || pos.q == width - 1 || pos.r == height - 1)
+            .collect::<Vec<_>>(),
+        OceanSources::Tiles(tiles) => tiles.iter().map(|&pos| map.wrap(pos)).collect(),
+    };
+
+    let mut ocean = HexMap::new(
+        map.width(),
+        map.height(),
+        vec![false; map.width() * map.height()],
+    );
+    let mut queue = VecDeque::new();
+    for pos in sources {
+        if sea.is_below(map.get(pos)) && !*ocean.get(pos) {
+            *ocean.get_mut_untracked(pos) = true;
+            queue.push_back(pos);
+        }
+    }
+    while let Some(pos) = queue.pop_front() {
+        for neighbor in map.wrapped_neighbors(pos) {
+            if sea.is_below(map.get(neighbor)) && !*ocean.get(neighbor) {
+                *ocean.get_mut_untracked(neighbor) = true;
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    for pos in map.positions() {
+        let tile = map.get(pos);
+        let kind = match (*ocean.get(pos), tile.kind) {
+            (true, _) => TileKind::Water,
+            (false, TileKind::Water) if !sea.is_below(tile) => TileKind::Rock,
+            (false, kind) => kind,
+        };
+        if tile.kind != kind {
+            let tile = map.get_mut(pos);
+            tile.kind = kind;
+            if kind == TileKind::Water {
+                tile.fire = Fire::None;
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use bevy::prelude::*;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    hexmap::{HexMap, HexPos},
+    influence::{Falloff, InfluenceMap, ResourceRichness, Territory},
+    rng::SurfaceRng,
+    schedule::SurfaceSystem,
+    simulation::{MyTileData, TileKind},
+    surfaces::Surfaces,
+};
+
+/// Settlements must be founded within this many steps of water
+const MAX_WATER_DISTANCE: u32 = 2;
+/// New settlements are founded between these distances from the one that spawned them
+const MIN_COLONY_DISTANCE: u32 = 4;
+const MAX_COLONY_DISTANCE: u32 = 8;
+/// Once a settlement grows past this it sends some of its people off to found a colony
+const COLONY_POPULATION: f32 = 400.0;
+const COLONY_FRACTION: f32 = 0.25;
+/// Chance per tick of a big enough settlement trying to found a colony
+const COLONY_CHANCE: f32 = 0.01;
+const FOUNDING_POPULATION: f32 = 20.0;
+/// Logistic growth rate per tick
+const GROWTH_RATE: f32 = 0.002;
+/// How many people one unit of food yield can feed
+const PEOPLE_PER_FOOD: f32 = 60.0;
+/// Chance per tick of someone settling an empty surface
+const SETTLE_EMPTY_CHANCE: f32 = 0.01;
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Settlement {
+    pub population: f32,
+}
+
+impl Settlement {
+    /// How far the settlement's territory reaches, grows with population
+    pub fn claim_radius(&self) -> u32 {
+        1 + (self.population / 100.0).sqrt() as u32
+    }
+}
+
+/// Which settlement owns each hex
+// Not Inspectable because HexMap isn't
+#[derive(Debug, Clone)]
+pub struct TerritoryClaims(pub HexMap<Option<Entity>>);
+
+// by hand so the entities keep their generations, see `snapshot::entity_bits`
+impl Serialize for TerritoryClaims {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let claims = &self.0;
+        let owners = (claims.positions())
+            .map(|pos| claims.get(pos).map(Entity::to_bits))
+            .collect::<Vec<_>>();
+        (claims.width(), claims.height(), owners).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TerritoryClaims {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (width, height, owners) =
+            <(usize, usize, Vec<Option<u64>>)>::deserialize(deserializer)?;
+        if owners.len() != width * height {
+            return Err(D::Error::custom("territory claims don't fit the map"));
+        }
+        let owners = owners.into_iter().map(|owner| owner.map(Entity::from_bits));
+        Ok(Self(HexMap::new(width, height, owners)))
+    }
+}
+
+/// Flat ground near water with nothing burning
+pub fn is_suitable(map: &HexMap<MyTileData>, pos: HexPos) -> bool {
+    let tile = map.get(pos);
+    tile.kind == TileKind::Rock
+        && !tile.fire.is_burning()
+        && map
+            .wrapped_neighbors(pos)
+            .all(|neighbor| map.get(neighbor).height.abs_diff(tile.height) <= 1)
+        && map
+            .within(pos, MAX_WATER_DISTANCE)
+            .any(|(pos, _)| map.get(pos).kind == TileKind::Water)
+}
+
+/// Food from the tiles within `radius` of `pos`, vegetation on land and fish in the water
+pub fn food_yield(map: &HexMap<MyTileData>, pos: HexPos, radius: u32) -> f32 {
+    map.within(pos, radius)
+        .map(|(pos, _)| {
+            let tile = map.get(pos);
+            match tile.kind {
+                TileKind::Water => 0.5,
+                TileKind::Rock => tile.vegetation.density,
+            }
+        })
+        .sum()
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+enum SettlementSystem {
+    Grow,
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| {
+            let map = world.resource::<HexMap<MyTileData>>();
+            let (width, height) = (map.width(), map.height());
+            world.insert_resource(TerritoryClaims(HexMap::new(
+                width,
+                height,
+                vec![None; width * height],
+            )));
+        })
+        .push_state_resource::<TerritoryClaims>()
+        .push_state_component::<Settlement>()
+        .push_system(settle_empty_surface)
+        .push_system(SurfaceSystem::new(grow_settlements).label(SettlementSystem::Grow))
+        .push_system(SurfaceSystem::new(claim_territory).after(SettlementSystem::Grow))
+        .push_system(SurfaceSystem::new(found_colonies).after(SettlementSystem::Grow))
+        .push_metric("settlements", |settlements: Query<&Settlement>| {
+            settlements.iter().count() as f32
+        })
+        .push_metric("total population", |settlements: Query<&Settlement>| {
+            // not `sum` which gives -0.0 when there aren't any
+            settlements
+                .iter()
+                .fold(0.0, |total, settlement| total + settlement.population)
+        });
+}
+
+fn settle_empty_surface(
+    mut cmds: Commands<'_, '_>,
+    map: Res<HexMap<MyTileData>>,
+    mut rng: ResMut<SurfaceRng>,
+    settlements: Query<(), With<Settlement>>,
+) {
+    let rng = rng.stream("settlements");
+    if !settlements.is_empty() || !rng.chance(SETTLE_EMPTY_CHANCE) {
+        return;
+    }
+
+    let candidates = map
+        .positions()
+        .filter(|&pos| is_suitable(&map, pos))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return;
+    }
+    let pos = candidates[rng.below(candidates.len() as u32) as usize];
+    cmds.spawn().insert_bundle((
+        pos,
+        Settlement {
+            population: FOUNDING_POPULATION,
+        },
+    ));
+}
+
+fn grow_settlements(
+    mut cmds: Commands<'_, '_>,
+    map: Res<HexMap<MyTileData>>,
+    mut settlements: Query<(Entity, &HexPos, &mut Settlement)>,
+) {
+    for (entity, &pos, mut settlement) in settlements.iter_mut() {
+        let capacity = food_yield(&map, pos, settlement.claim_radius()) * PEOPLE_PER_FOOD;
+        let population = settlement.population;
+        settlement.population += match capacity > 0.0 {
+            true => GROWTH_RATE * population * (1.0 - population / capacity),
+            false => -GROWTH_RATE * population,
+        };
+        // abandoned, or the land under it stopped being habitable
+        if settlement.population < 1.0 || map.get(pos).kind == TileKind::Water {
+            cmds.entity(entity).despawn();
+        }
+    }
+}
+
+fn claim_territory(
+    map: Res<HexMap<MyTileData>>,
+    mut claims: ResMut<TerritoryClaims>,
+    mut territory: ResMut<InfluenceMap<Territory>>,
+    settlements: Query<(Entity, &HexPos, &Settlement)>,
+) {
+    // closest settlement gets the hex, ties go to the bigger one
+    let mut best = HexMap::new(
+        map.width(),
+        map.height(),
+        vec![None::<(u32, f32, Entity)>; map.width() * map.height()],
+    );
+    for (entity, &pos, settlement) in settlements.iter() {
+        for (claimed, distance) in map.within(pos, settlement.claim_radius()) {
+            let current = best.get_mut_untracked(claimed);
+            let wins = match *current {
+                None => true,
+                Some((other_distance, other_population, _)) => {
+                    (distance, -settlement.population) < (other_distance, -other_population)
+                }
+            };
+            if wins {
+                *current = Some((distance, settlement.population, entity));
+            }
+        }
+    }
+    for pos in map.positions() {
+        *claims.0.get_mut_untracked(pos) = best.get(pos).map(|(_, _, entity)| entity);
+    }
+
+    territory.decay(0.99);
+    territory.propagate(
+        settlements
+            .iter()
+            .map(|(_, &pos, settlement)| (pos, settlement.population * 0.0001)),
+        Falloff::Linear,
+        MAX_COLONY_DISTANCE,
+    );
+}
+
+fn found_colonies(
+    mut cmds: Commands<'_, '_>,
+    map: Res<HexMap<MyTileData>>,
+    richness: Res<InfluenceMap<ResourceRichness>>,
+    mut rng: ResMut<SurfaceRng>,
+    mut settlements: Query<(&HexPos, &mut Settlement)>,
+) {
+    let rng = rng.stream("settlements");
+    let existing = settlements.iter().map(|(&pos, _)| pos).collect::<Vec<_>>();
+    let mut founded = vec![];
+
+    for (&pos, mut settlement) in settlements.iter_mut() {
+        if settlement.population < COLONY_POPULATION || !rng.chance(COLONY_CHANCE) {
+            continue;
+        }
+        let candidates = map
+            .within(pos, MAX_COLONY_DISTANCE)
+            .filter(|&(_, distance)| distance >= MIN_COLONY_DISTANCE)
+            .map(|(candidate, _)| candidate)
+            .filter(|&candidate| {
+                existing
+                    .iter()
+                    .chain(founded.iter())
+                    .all(|&other| map.wrapped_distance(candidate, other) >= MIN_COLONY_DISTANCE)
+            })
+            .filter(|&candidate| is_suitable(&map, candidate));
+        // settlers head for the richest land they can reach, the closest of it on a tie
+        let Some(colony) = candidates.min_by(|&a, &b| richness.get(b).total_cmp(&richness.get(a)))
+        else {
+            continue;
+        };
+
+        let settlers = settlement.population * COLONY_FRACTION;
+        settlement.population -= settlers;
+        founded.push(colony);
+        cmds.spawn().insert_bundle((
+            colony,
+            Settlement {
+                population: settlers,
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geology::Lava, vegetation::Vegetation, wildfire::Fire};
+
+    #[test]
+    fn colonies_are_founded_on_the_richest_land() {
+        // flat land with a strip of sea down the `q == 0` edge
+        let (width, height) = (20, 20);
+        let tiles = (0..width * height).map(|i| MyTileData {
+            height: 0,
+            kind: match i % width {
+                0 => TileKind::Water,
+                _ => TileKind::Rock,
+            },
+            vegetation: Vegetation::default(),
+            fire: Fire::default(),
+            deposit: None,
+            lava: Lava::default(),
+            snow: false,
+        });
+        let mut richness = InfluenceMap::<ResourceRichness>::new(width, height);
+        let richest = HexPos { q: 1, r: 4 };
+        richness.set(richest, 5.0);
+        let mut world = World::new();
+        world.insert_resource(HexMap::new(width, height, tiles));
+        world.insert_resource(richness);
+        world.insert_resource(SurfaceRng::new(0, 0));
+        let home = HexPos { q: 2, r: 10 };
+        world
+            .spawn()
+            .insert_bundle((home, Settlement { population: 1000.0 }));
+
+        let mut system = IntoSystem::into_system(found_colonies);
+        system.initialize(&mut world);
+        let mut settlements = world.query::<(&HexPos, &Settlement)>();
+        for _ in 0..2000 {
+            system.run((), &mut world);
+            system.apply_buffers(&mut world);
+            if settlements.iter(&world).count() > 1 {
+                break;
+            }
+        }
+        let colonies = (settlements.iter(&world))
+            .map(|(&pos, _)| pos)
+            .filter(|&pos| pos != home)
+            .collect::<Vec<_>>();
+        assert_eq!(colonies, [richest]);
+    }
+}
@@ -1,19 +1,45 @@
 use crate::{
-    hexmap::HexMap,
-    surfaces::{SelectedSurface, Surfaces},
+    agents, ai, calendar,
+    economy::{self, Deposit},
+    flowfield,
+    geology::{self, Lava},
+    hexmap::{HexMap, HexPos},
+    history, influence, invariants,
+    schedule::{SurfaceStage, SurfaceSystem},
+    sealevel, settlements,
+    surfaces::{SelectedSurface, SurfaceId, Surfaces, TickRate},
+    vegetation::{self, Vegetation},
+    wildfire::{self, Fire},
     AppState,
 };
 use bevy::prelude::*;
-use bevy_inspector_egui::Inspectable;
+use bevy_inspector_egui::{bevy_egui::EguiContext, egui, Inspectable};
 use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Inspectable)]
+#[derive(Debug, Clone, PartialEq, Inspectable, Serialize, Deserialize)]
 pub struct MyTileData {
     pub height: u8,
     pub kind: TileKind,
+    pub vegetation: Vegetation,
+    pub fire: Fire,
+    pub deposit: Option<Deposit>,
+    pub lava: Lava,
+    pub snow: bool,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Inspectable)]
+impl MyTileData {
+    /// How well the tile resists being worn away, plant roots hold soil together
+    pub fn erosion_resistance(&self) -> f32 {
+        let base = match self.kind {
+            TileKind::Water => 0.0,
+            TileKind::Rock => 0.5,
+        };
+        base + 0.5 * self.vegetation.density * self.vegetation.plant.root_strength()
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Inspectable, Serialize, Deserialize)]
 pub enum TileKind {
     Water,
     Rock,
@@ -22,18 +48,55 @@ pub enum TileKind {
 impl TileKind {
     pub fn material_name(&self) -> &'static str {
         match self {
-            Self::Water => "Water",
+            // drawn as the sea bed, the water itself is a plane at the sea level
+            Self::Water => "Rock",
             Self::Rock => "Rock",
         }
     }
 }
 
+/// Sent in a surface world at the end of each tick for every tile that ended up different
+#[derive(Debug, Clone)]
+pub struct TileChanged {
+    pub pos: HexPos,
+    pub old: MyTileData,
+    pub new: MyTileData,
+}
+
+/// A `TileChanged` drained from a surface world into the main world
+#[derive(Debug, Clone)]
+pub struct SurfaceTileChanged {
+    pub surface: SurfaceId,
+    pub change: TileChanged,
+}
+
 pub fn init_app(app: &mut App) {
-    app.add_enter_system(AppState::Loading, init_map)
-        .add_system(simulate_surfaces.run_in_state(AppState::Playing));
+    app.add_event::<SurfaceTileChanged>()
+        .add_enter_system(AppState::Loading, init_map)
+        .add_system(
+            simulate_surfaces
+                .run_in_state(AppState::Playing)
+                .run_if(history::is_live),
+        )
+        .add_system(reselect_removed_surface.run_in_state(AppState::Playing))
+        .init_resource::<DormantWhenUnselected>()
+        .add_system(wake_selected_surface.run_in_state(AppState::Playing))
+        .add_system(tick_rate_window.run_in_state(AppState::Playing));
 }
 
 fn init_map(mut cmds: Commands<'_, '_>) {
+    let seed = master_seed();
+    info!("simulation master seed: {seed} (set HEXY_SEED to replay)");
+    let (mut surfaces, surface) = new_surfaces(seed);
+    if let Ok(dir) = std::env::var("HEXY_DUMP_DIR") {
+        surfaces.set_invariant_checks(true, Some(dir.into()));
+    }
+    cmds.insert_resource(surfaces);
+    cmds.insert_resource(SelectedSurface(surface));
+}
+
+/// The starting surface with every system pushed, the same seed always gives the same surfaces
+pub fn new_surfaces(seed: u64) -> (Surfaces, SurfaceId) {
     let map = HexMap::new(
         16,
         16,
@@ -41,27 +104,250 @@ fn init_map(mut cmds: Commands<'_, '_>) {
             let mut i = 0;
             std::iter::from_fn(move || {
                 i += 1;
+                let island = i % 6 == 0 && i > 100 && i < 150;
                 Some(MyTileData {
-                    height: (i % 6) as u8,
-                    kind: if i % 6 == 0 && i > 100 && i < 150 {
+                    // islands poke out above the default `SeaLevel`
+                    height: if island { 3 } else { (i % 3) as u8 },
+                    kind: if island {
                         TileKind::Rock
                     } else {
                         TileKind::Water
                     },
+                    vegetation: Vegetation::default(),
+                    fire: Fire::default(),
+                    deposit: None,
+                    lava: Lava::default(),
+                    snow: false,
                 })
             })
         }
         .take(16 * 16),
     );
-    let mut surfaces = Surfaces::new();
-    surfaces.new_surface(World::new(), map);
+    let mut surfaces = Surfaces::new(seed);
+    let mut world = World::new();
+    for pos in map.positions() {
+        if map.get(pos).kind == TileKind::Rock {
+            agents::spawn_agent(&mut world, pos);
+        }
+    }
+    let surface = surfaces.new_surface(world, map);
     add_systems(&mut surfaces);
-    cmds.insert_resource(surfaces);
-    cmds.insert_resource(SelectedSurface(0));
+    invariants::add_invariants(&mut surfaces);
+    (surfaces, surface)
+}
+
+/// Reads the seed from the `HEXY_SEED` env var so a bug report's seed can be replayed exactly,
+/// otherwise picks one from the clock (it gets logged either way)
+fn master_seed() -> u64 {
+    match std::env::var("HEXY_SEED") {
+        Ok(seed) => seed.parse().expect("HEXY_SEED should be a u64"),
+        Err(_) => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64,
+    }
+}
+
+/// Moves the selection to some other surface when the selected one gets removed
+fn reselect_removed_surface(surfaces: Res<Surfaces>, mut selected: ResMut<SelectedSurface>) {
+    if surfaces.contains(selected.0) {
+        return;
+    }
+    if let Some(surface) = surfaces.ids().next() {
+        warn!(
+            "selected surface {} was removed, selecting {surface}",
+            selected.0
+        );
+        selected.0 = surface;
+    }
+}
+
+/// Whether surfaces go dormant when they stop being the selected one, see `Surfaces::set_dormant`
+#[derive(Debug, Default, Inspectable)]
+pub struct DormantWhenUnselected(pub bool);
+
+/// Switching to a surface wakes it back up to its own rate, `previous` is the surface selected
+/// before the switch
+fn wake_selected_surface(
+    selected: Res<SelectedSurface>,
+    dormant_when_unselected: Res<DormantWhenUnselected>,
+    mut surfaces: ResMut<Surfaces>,
+    mut previous: Local<Option<SurfaceId>>,
+) {
+    if !selected.is_changed() || *previous == Some(selected.0) {
+        return;
+    }
+    if let Some(previous) = *previous {
+        if dormant_when_unselected.0 {
+            surfaces.set_dormant(previous, true);
+        }
+    }
+    surfaces.set_dormant(selected.0, false);
+    *previous = Some(selected.0);
 }
 
-fn simulate_surfaces(mut surfaces: ResMut<Surfaces>) {
+/// Sets how fast the selected surface steps and whether it's dormant
+fn tick_rate_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut surfaces: ResMut<Surfaces>,
+    selected: Res<SelectedSurface>,
+    mut dormant_when_unselected: ResMut<DormantWhenUnselected>,
+) {
+    let Some(mut rate) = surfaces.tick_rate(selected.0) else {
+        return;
+    };
+    let mut dormant = surfaces.is_dormant(selected.0);
+
+    egui::Window::new("Tick rate").show(egui_ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut rate.ticks).clamp_range(0..=100));
+            ui.label("ticks every");
+            ui.add(egui::DragValue::new(&mut rate.steps).clamp_range(1..=1000));
+            ui.label("steps");
+        });
+        ui.horizontal(|ui| {
+            let (label, toggled) = match rate.is_paused() {
+                true => ("Unpause", TickRate::NORMAL),
+                false => ("Pause", TickRate::PAUSED),
+            };
+            if ui.button(label).clicked() {
+                rate = toggled;
+            }
+            ui.checkbox(&mut dormant, "Dormant");
+        });
+        ui.checkbox(
+            &mut dormant_when_unselected.0,
+            "Unselected surfaces go dormant",
+        );
+    });
+
+    if Some(rate) != surfaces.tick_rate(selected.0) {
+        surfaces.set_tick_rate(selected.0, rate);
+    }
+    surfaces.set_dormant(selected.0, dormant);
+}
+
+fn simulate_surfaces(
+    mut surfaces: ResMut<Surfaces>,
+    mut tile_changes: EventWriter<'_, '_, SurfaceTileChanged>,
+) {
     surfaces.simulate_step();
+    for surface in surfaces.ids().collect::<Vec<_>>() {
+        let changes = surfaces.drain_tile_changes(surface);
+        tile_changes.send_batch(changes.map(|change| SurfaceTileChanged { surface, change }));
+    }
+}
+
+/// Should be the last surface system so every change made during the tick gets reported.
+/// Changes made to the map from outside the schedule get reported at the end of the next tick.
+fn emit_tile_changes(
+    mut map: ResMut<HexMap<MyTileData>>,
+    mut tile_changes: EventWriter<'_, '_, TileChanged>,
+) {
+    let changes = map.take_changes().collect::<Vec<_>>();
+    for (pos, old) in changes {
+        let new = map.get(pos);
+        if *new != old {
+            tile_changes.send(TileChanged {
+                pos,
+                old,
+                new: new.clone(),
+            });
+        }
+    }
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    calendar::add_systems(surfaces);
+    vegetation::add_systems(surfaces);
+    wildfire::add_systems(surfaces);
+    geology::add_systems(surfaces);
+    sealevel::add_systems(surfaces);
+    agents::add_systems(surfaces);
+    flowfield::add_systems(surfaces);
+    influence::add_systems(surfaces);
+    settlements::add_systems(surfaces);
+    economy::add_systems(surfaces);
+    ai::add_systems(surfaces);
+    surfaces
+        .push_world_init(|world| world.init_resource::<Events<TileChanged>>())
+        .push_state_component::<HexPos>()
+        .push_system(
+            SurfaceSystem::new(emit_tile_changes)
+                .in_stage(SurfaceStage::PostSim)
+                .when_dormant(),
+        )
+        .push_metric("mean height", mean_height)
+        .push_metric("water tiles", water_tiles);
+}
+
+fn mean_height(map: Res<HexMap<MyTileData>>) -> f32 {
+    let total = map
+        .positions()
+        .map(|pos| map.get(pos).height as f32)
+        .sum::<f32>();
+    total / (map.width() * map.height()) as f32
+}
+
+fn water_tiles(map: Res<HexMap<MyTileData>>) -> f32 {
+    map.positions()
+        .filter(|&pos| map.get(pos).kind == TileKind::Water)
+        .count() as f32
 }
 
-pub fn add_systems(_surfaces: &mut Surfaces) {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `TileChanged`s `emit_tile_changes` sends after `edit`
+    fn tile_changes(edit: impl FnOnce(&mut HexMap<MyTileData>)) -> Vec<TileChanged> {
+        let tile = MyTileData {
+            height: 1,
+            kind: TileKind::Rock,
+            vegetation: Vegetation::default(),
+            fire: Fire::default(),
+            deposit: None,
+            lava: Lava::default(),
+            snow: false,
+        };
+        let mut world = World::new();
+        world.insert_resource(HexMap::new(2, 2, vec![tile; 4]));
+        world.init_resource::<Events<TileChanged>>();
+        edit(&mut world.resource_mut());
+
+        let mut system = IntoSystem::into_system(emit_tile_changes);
+        system.initialize(&mut world);
+        system.run((), &mut world);
+        let events = world.resource::<Events<TileChanged>>();
+        events.get_reader().iter(events).cloned().collect()
+    }
+
+    #[test]
+    fn changed_tiles_are_reported_once() {
+        let pos = HexPos { q: 1, r: 0 };
+        let changes = tile_changes(|map| {
+            map.get_mut(pos).height = 2;
+            map.get_mut(pos).snow = true;
+        });
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].pos, pos);
+        assert_eq!((changes[0].old.height, changes[0].old.snow), (1, false));
+        assert_eq!((changes[0].new.height, changes[0].new.snow), (2, true));
+    }
+
+    #[test]
+    fn tiles_changed_back_are_not_reported() {
+        let pos = HexPos { q: 0, r: 1 };
+        let changes = tile_changes(|map| {
+            map.get_mut(pos).height = 2;
+            map.get_mut(pos).height = 1;
+        });
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn untracked_changes_are_not_reported() {
+        let changes = tile_changes(|map| map.get_mut_untracked(HexPos { q: 0, r: 0 }).height = 2);
+        assert!(changes.is_empty());
+    }
+}
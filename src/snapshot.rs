@@ -0,0 +1,258 @@
+use bevy::{ecs::system::Resource, prelude::*};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    hexmap::{HexMap, HexPos},
+    save::SaveError,
+    simulation::MyTileData,
+};
+
+/// A registered resource, saves refer to it by `name`
+struct ResourceType {
+    name: &'static str,
+    copy: fn(&World, &mut World),
+    save: fn(&World) -> Option<ron::Result<String>>,
+    load: fn(&str, &mut World) -> ron::Result<()>,
+}
+
+/// A registered component, saves refer to it by `name`
+struct ComponentType {
+    name: &'static str,
+    copy: fn(&World, Entity, &mut World),
+    save: fn(&World, Entity) -> Option<ron::Result<String>>,
+    load: fn(&str, Entity, &mut World) -> ron::Result<()>,
+}
+
+/// `(type name, value as RON)` of every registered type a world or entity has
+pub type SavedValues = Vec<(String, String)>;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedEntity {
+    /// `Entity::to_bits`
+    pub entity: u64,
+    pub components: SavedValues,
+}
+
+/// The resources and components that make up a surface's state, see `Surfaces::push_state_resource`.
+/// Resources that aren't registered, e.g. ones holding systems or closures, come from the world inits instead.
+///
+/// Values go through serde rather than bevy reflection: every state type already derives serde, bevy
+/// 0.8 can't reflect some of them (e.g. `HexMap`'s `Box<[T]>`), and the hand written impls keep
+/// entity generations that bevy's own serialization drops, see `entity_bits`.
+// Not Inspectable because of the fn pointers
+#[derive(Default)]
+pub struct StateTypes {
+    resources: Vec<ResourceType>,
+    components: Vec<ComponentType>,
+}
+
+impl StateTypes {
+    pub fn add_resource<T: Resource + Clone + Serialize + DeserializeOwned>(&mut self) {
+        self.resources.push(ResourceType {
+            name: std::any::type_name::<T>(),
+            copy: |source, destination| {
+                if let Some(resource) = source.get_resource::<T>() {
+                    destination.insert_resource(resource.clone());
+                }
+            },
+            save: |world| world.get_resource::<T>().map(ron::to_string),
+            load: |saved, world| {
+                world.insert_resource(ron::from_str::<T>(saved)?);
+                Ok(())
+            },
+        });
+    }
+
+    pub fn add_component<T: Component + Clone + Serialize + DeserializeOwned>(&mut self) {
+        self.components.push(ComponentType {
+            name: std::any::type_name::<T>(),
+            copy: |source, entity, destination| {
+                if let Some(component) = source.get::<T>(entity) {
+                    destination.entity_mut(entity).insert(component.clone());
+                }
+            },
+            save: |world, entity| world.get::<T>(entity).map(ron::to_string),
+            load: |saved, entity, world| {
+                world.entity_mut(entity).insert(ron::from_str::<T>(saved)?);
+                Ok(())
+            },
+        });
+    }
+
+    /// Copies every registered resource, replacing any `destination` already has
+    pub fn copy_resources(&self, source: &World, destination: &mut World) {
+        for resource in self.resources.iter() {
+            (resource.copy)(source, destination);
+        }
+    }
+
+    /// Copies every entity with whichever registered components it has. Entities keep their ids so
+    /// anything storing an `Entity` still points at the right one, and are spawned in the order
+    /// they're stored in `source` so queries mostly visit them in the same order, and the free ids are
+    /// freed the same so new entities get the same ids too. `destination` shouldn't have any
+    /// entities yet.
+    pub fn copy_entities(&self, source: &World, destination: &mut World) {
+        for entity in stored_entities(source) {
+            destination
+                .get_or_spawn(entity)
+                .expect("copied entities onto a world that already has entities");
+            for component in self.components.iter() {
+                (component.copy)(source, entity, destination);
+            }
+        }
+        let free = free_entities(source);
+        assert!(
+            free_like(&free, destination),
+            "copied entities onto a world that already has entities"
+        );
+    }
+
+    pub fn save_resources(&self, world: &World) -> ron::Result<SavedValues> {
+        let mut saved = vec![];
+        for resource in self.resources.iter() {
+            if let Some(value) = (resource.save)(world) {
+                saved.push((resource.name.to_owned(), value?));
+            }
+        }
+        Ok(saved)
+    }
+
+    /// Same as `copy_resources` but from a save
+    pub fn load_resources(&self, saved: &SavedValues, world: &mut World) -> Result<(), SaveError> {
+        for (name, value) in saved.iter() {
+            let resource = (self.resources.iter())
+                .find(|resource| resource.name == name)
+                .ok_or_else(|| SaveError::Mismatch(format!("unknown resource {name}")))?;
+            (resource.load)(value, world)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_entities(&self, world: &World) -> ron::Result<Vec<SavedEntity>> {
+        let mut saved = vec![];
+        for entity in stored_entities(world) {
+            let mut components = vec![];
+            for component in self.components.iter() {
+                if let Some(value) = (component.save)(world, entity) {
+                    components.push((component.name.to_owned(), value?));
+                }
+            }
+            saved.push(SavedEntity {
+                entity: entity.to_bits(),
+                components,
+            });
+        }
+        Ok(saved)
+    }
+
+    /// Same as `copy_entities` but from a save
+    pub fn load_entities(&self, saved: &[SavedEntity], world: &mut World) -> Result<(), SaveError> {
+        for SavedEntity { entity, components } in saved.iter() {
+            let entity = Entity::from_bits(*entity);
+            if world.get_or_spawn(entity).is_none() {
+                return Err(SaveError::Mismatch(format!(
+                    "{entity:?} is in the save twice"
+                )));
+            }
+            for (name, value) in components.iter() {
+                let component = (self.components.iter())
+                    .find(|component| component.name == name)
+                    .ok_or_else(|| SaveError::Mismatch(format!("unknown component {name}")))?;
+                (component.load)(value, entity, world)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Makes bevy hand freed entity ids out again lowest id first. It normally reuses whichever was
+/// freed last, so which ids new entities get depends on the order things were despawned in, which
+/// a copy or save of the world can't see. After this they only depend on `free_entities`, which
+/// `free_like` can put on another world. Bumps the generation of every free id.
+pub fn sort_free_entities(world: &mut World) {
+    let free = world.entities().meta_len() - world.entities().len() as usize;
+    let mut taken = (0..free).map(|_| world.spawn().id()).collect::<Vec<_>>();
+    // the last one despawned is the first one handed out again
+    taken.sort_by_key(|entity| std::cmp::Reverse(entity.id()));
+    for entity in taken {
+        world.despawn(entity);
+    }
+}
+
+/// Ids that are free to reuse, with the generation they'll have when they are, lowest id first
+pub fn free_entities(world: &World) -> Vec<Entity> {
+    let entities = world.entities();
+    (0..entities.meta_len() as u32)
+        .filter_map(|id| entities.resolve_from_id(id))
+        .filter(|&entity| entities.get(entity).is_none())
+        .collect()
+}
+
+/// Frees the ids `free_entities` gave for another world, in the order `sort_free_entities` leaves
+/// them in. Call it once `world` has all its entities, false if an id is already taken.
+pub fn free_like(free: &[Entity], world: &mut World) -> bool {
+    let mut taken = vec![];
+    for entity in free.iter() {
+        // freeing bumps the generation, so take the id with the one before
+        let Some(generation) = entity.generation().checked_sub(1) else {
+            return false;
+        };
+        let before = Entity::from_bits((generation as u64) << 32 | entity.id() as u64);
+        if (world.entities().resolve_from_id(entity.id()))
+            .is_some_and(|existing| world.entities().get(existing).is_some())
+        {
+            return false;
+        }
+        world.get_or_spawn(before);
+        taken.push(before);
+    }
+    for &entity in taken.iter().rev() {
+        world.despawn(entity);
+    }
+    true
+}
+
+/// Every entity in the order they're stored, archetype by archetype
+fn stored_entities(world: &World) -> impl Iterator<Item = Entity> + '_ {
+    (world.archetypes().iter()).flat_map(|archetype| archetype.entities().iter().copied())
+}
+
+/// For `#[serde(with = "snapshot::entity_bits")]` on `Entity` fields, bevy's own impls leave out the
+/// generation so a stale `Entity` could end up pointing at whatever reused its index
+pub mod entity_bits {
+    use bevy::prelude::Entity;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(entity: &Entity, serializer: S) -> Result<S::Ok, S::Error> {
+        entity.to_bits().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Entity, D::Error> {
+        u64::deserialize(deserializer).map(Entity::from_bits)
+    }
+}
+
+/// A tile that's different between two surfaces
+#[derive(Debug, Clone)]
+pub struct TileDiff {
+    pub pos: HexPos,
+    pub a: MyTileData,
+    pub b: MyTileData,
+}
+
+/// Every tile that's different between `a` and `b`, `None` if the maps aren't the same size
+pub fn diff_tiles(a: &HexMap<MyTileData>, b: &HexMap<MyTileData>) -> Option<Vec<TileDiff>> {
+    if (a.width(), a.height()) != (b.width(), b.height()) {
+        return None;
+    }
+    let diffs = a
+        .positions()
+        .filter(|&pos| a.get(pos) != b.get(pos))
+        .map(|pos| TileDiff {
+            pos,
+            a: a.get(pos).clone(),
+            b: b.get(pos).clone(),
+        })
+        .collect();
+    Some(diffs)
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt::Write as _,
+};
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{
+    bevy_egui::EguiContext,
+    egui::{
+        self,
+        plot::{Legend, Line, Plot, PlotPoints},
+    },
+};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    surfaces::{SelectedSurface, Surfaces},
+    AppState,
+};
+
+/// A system that reads a surface world and boils it down to one number, see `Surfaces::push_metric`
+pub type Metric = Box<dyn System<In = (), Out = f32> + Send + Sync>;
+
+/// One metric and its most recent samples
+pub struct Series {
+    name: String,
+    metric: Metric,
+    /// `(tick, value)`, oldest first
+    samples: VecDeque<(u64, f32)>,
+}
+
+impl Series {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn samples(&self) -> impl DoubleEndedIterator<Item = (u64, f32)> + '_ {
+        self.samples.iter().copied()
+    }
+}
+
+/// Every metric registered on a surface, sampled every `sample_every` ticks and keeping the
+/// last `capacity` samples of each
+// Not Inspectable because of the metric systems
+pub struct Stats {
+    pub sample_every: u64,
+    pub capacity: usize,
+    series: Vec<Series>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            sample_every: 10,
+            capacity: 1000,
+            series: vec![],
+        }
+    }
+}
+
+impl Stats {
+    /// `metric` has to be initialized on the world it'll be sampled from
+    pub fn add_metric(&mut self, name: String, metric: Metric) {
+        self.series.push(Series {
+            name,
+            metric,
+            samples: VecDeque::new(),
+        });
+    }
+
+    pub fn series(&self) -> impl Iterator<Item = &Series> {
+        self.series.iter()
+    }
+
+    /// Takes the settings and samples of every metric in `other` with the same name as one of ours
+    pub fn copy_samples(&mut self, other: &Stats) {
+        self.sample_every = other.sample_every;
+        self.capacity = other.capacity;
+        for series in self.series.iter_mut() {
+            if let Some(other) = other.series().find(|other| other.name == series.name) {
+                series.samples = other.samples.clone();
+            }
+        }
+    }
+
+    /// Drops the samples taken from `tick` on, for when the surface gets rewound
+    pub fn forget_from(&mut self, tick: u64) {
+        for series in self.series.iter_mut() {
+            series.samples.retain(|&(sampled, _)| sampled < tick);
+        }
+    }
+
+    /// One row per sampled tick, one column per metric. Metrics registered after sampling
+    /// started have empty cells for the ticks before them.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("tick");
+        for series in self.series.iter() {
+            write!(csv, ",{}", series.name).unwrap();
+        }
+        csv.push('\n');
+
+        let mut rows = BTreeMap::<u64, Vec<Option<f32>>>::new();
+        for (column, series) in self.series.iter().enumerate() {
+            for (tick, value) in series.samples() {
+                rows.entry(tick)
+                    .or_insert_with(|| vec![None; self.series.len()])[column] = Some(value);
+            }
+        }
+        for (tick, values) in rows {
+            write!(csv, "{tick}").unwrap();
+            for value in values {
+                csv.push(',');
+                if let Some(value) = value {
+                    write!(csv, "{value}").unwrap();
+                }
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// `{"sample_every": n, "series": [{"name": "...", "samples": [[tick, value], ...]}, ...]}`,
+    /// values that aren't finite are written as `null`
+    pub fn to_json(&self) -> String {
+        let exported = ExportedStats {
+            sample_every: self.sample_every,
+            series: (self.series.iter())
+                .map(|series| ExportedSeries {
+                    name: series.name.clone(),
+                    samples: (series.samples())
+                        .map(|(tick, value)| (tick, value.is_finite().then_some(value)))
+                        .collect(),
+                })
+                .collect(),
+        };
+        serde_json::to_string(&exported).unwrap()
+    }
+}
+
+/// What `Stats::to_json` writes
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedStats {
+    pub sample_every: u64,
+    pub series: Vec<ExportedSeries>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedSeries {
+    pub name: String,
+    /// `(tick, value)`, `None` where the value wasn't finite
+    pub samples: Vec<(u64, Option<f32>)>,
+}
+
+/// Runs every metric on `world` if `tick` is one that should be sampled
+pub fn sample(tick: u64, world: &mut World) {
+    world.resource_scope(|world, mut stats: Mut<Stats>| {
+        if !tick.is_multiple_of(stats.sample_every.max(1)) {
+            return;
+        }
+        let capacity = stats.capacity;
+        for series in stats.series.iter_mut() {
+            series.metric.update_archetype_component_access(world);
+            let value = series.metric.run((), world);
+            while series.samples.len() >= capacity.max(1) {
+                series.samples.pop_front();
+            }
+            series.samples.push_back((tick, value));
+        }
+    });
+}
+
+/// Same as `SurfaceSchedule::check_change_ticks` but for the metric systems
+pub fn check_change_ticks(world: &mut World, change_tick: u32) {
+    for series in world.resource_mut::<Stats>().series.iter_mut() {
+        series.metric.check_change_tick(change_tick);
+    }
+}
+
+pub fn init_app(app: &mut App) {
+    app.add_system(stats_window.run_in_state(AppState::Playing));
+}
+
+/// Plots the selected surface's metrics, `hidden` are the ones that have been unticked
+fn stats_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    surfaces: Res<Surfaces>,
+    selected: Res<SelectedSurface>,
+    mut hidden: Local<HashSet<String>>,
+) {
+    let Some(world) = surfaces.get(selected.0) else {
+        return;
+    };
+    let stats = world.resource::<Stats>();
+
+    egui::Window::new("Stats").show(egui_ctx.ctx_mut(), |ui| {
+        ui.label(format!("sampled every {} ticks", stats.sample_every));
+        ui.horizontal_wrapped(|ui| {
+            for series in stats.series() {
+                let mut shown = !hidden.contains(series.name());
+                if ui.checkbox(&mut shown, series.name()).changed() {
+                    match shown {
+                        true => hidden.remove(series.name()),
+                        false => hidden.insert(series.name().to_owned()),
+                    };
+                }
+            }
+        });
+
+        Plot::new("stats_plot")
+            .legend(Legend::default())
+            .height(200.0)
+            .show(ui, |plot_ui| {
+                for series in stats.series() {
+                    if hidden.contains(series.name()) {
+                        continue;
+                    }
+                    let points = series
+                        .samples()
+                        .map(|(tick, value)| [tick as f64, value as f64])
+                        .collect::<Vec<_>>();
+                    plot_ui.line(Line::new(PlotPoints::new(points)).name(series.name()));
+                }
+            });
+
+        ui.horizontal(|ui| {
+            let export = match (
+                ui.button("Export CSV").clicked(),
+                ui.button("Export JSON").clicked(),
+            ) {
+                (true, _) => Some(("csv", stats.to_csv())),
+                (_, true) => Some(("json", stats.to_json())),
+                _ => None,
+            };
+            if let Some((extension, contents)) = export {
+                let path = format!("stats_surface_{}.{extension}", selected.0);
+                match std::fs::write(&path, contents) {
+                    Ok(()) => info!("wrote stats to {path}"),
+                    Err(err) => error!("couldn't write stats to {path}: {err}"),
+                }
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `sea level` only started being sampled at tick 10
+    fn known_stats() -> Stats {
+        let mut stats = Stats::default();
+        for (name, samples) in [
+            ("agents", vec![(0, 1.0), (10, 2.5), (20, 4.0)]),
+            ("sea level", vec![(10, -0.5), (20, 0.25)]),
+        ] {
+            stats.add_metric(name.to_owned(), Box::new(IntoSystem::into_system(|| 0.0)));
+            stats.series.last_mut().unwrap().samples = samples.into();
+        }
+        stats
+    }
+
+    fn samples(stats: &Stats) -> Vec<(String, Vec<(u64, f32)>)> {
+        (stats.series())
+            .map(|series| (series.name().to_owned(), series.samples().collect()))
+            .collect()
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let stats = known_stats();
+        let csv = stats.to_csv();
+        let mut lines = csv.lines();
+        let mut columns = (lines.next().unwrap().split(',').skip(1))
+            .map(|name| (name.to_owned(), vec![]))
+            .collect::<Vec<_>>();
+        for line in lines {
+            let mut cells = line.split(',');
+            let tick = cells.next().unwrap().parse().unwrap();
+            for ((_, samples), cell) in columns.iter_mut().zip(cells) {
+                if !cell.is_empty() {
+                    samples.push((tick, cell.parse().unwrap()));
+                }
+            }
+        }
+        assert_eq!(columns, samples(&stats));
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let mut stats = known_stats();
+        let json = stats.to_json();
+        let exported = serde_json::from_str::<ExportedStats>(&json).unwrap();
+        assert_eq!(exported.sample_every, stats.sample_every);
+        let series = (exported.series.into_iter())
+            .map(|series| {
+                let samples = (series.samples.into_iter())
+                    .map(|(tick, value)| (tick, value.unwrap()))
+                    .collect();
+                (series.name, samples)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(series, samples(&stats));
+
+        stats.series[0].samples.push_back((30, f32::NAN));
+        let exported = serde_json::from_str::<ExportedStats>(&stats.to_json()).unwrap();
+        assert_eq!(exported.series[0].samples.last(), Some(&(30, None)));
+    }
+}
@@ -1,112 +1,1329 @@
-use std::marker::PhantomData;
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fmt,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy::{
+    ecs::{
+        change_detection::CHECK_TICK_THRESHOLD,
+        system::{Resource, SystemParam},
+    },
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
 use bevy_inspector_egui::Inspectable;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{hexmap::HexMap, simulation::MyTileData};
+use crate::{
+    hexmap::HexMap,
+    history::{History, HistorySettings, Timeline},
+    invariants::{self, Invariant, InvariantReport, Invariants, Violation},
+    rng::SurfaceRng,
+    save::{SaveError, SaveFile, SavedSurface, SurfaceManifest, SAVE_VERSION},
+    schedule::{IntoSurfaceSystem, SurfaceSchedule, SurfaceStage, SurfaceSystem},
+    simulation::{MyTileData, TileChanged},
+    snapshot::{self, SavedEntity, SavedValues, StateTypes, TileDiff},
+    stats::{self, Stats},
+};
 
-/// Runs a bunch of systems sequentially with no parallelism, flushing commands after each system runs
-// Not Inspectable because...Rust magic?
-pub struct SimpleSchedule {
-    systems: Vec<Box<dyn System<In = (), Out = ()> + Send + Sync>>,
+type WorldInit = Box<dyn Fn(&mut World) + Send + Sync>;
+
+/// Which surfaces a system pushed onto `Surfaces` gets added to
+enum SystemTarget {
+    All,
+    Tagged(String),
 }
 
-impl SimpleSchedule {
-    /// this fn will handle initializing `system`
-    pub fn add_system(
-        &mut self,
-        mut system: Box<dyn System<In = (), Out = ()> + Send + Sync>,
-        world: &mut World,
-    ) -> &mut Self {
-        system.initialize(world);
-        self.systems.push(system);
+/// Handle to a surface in `Surfaces`, stays valid until that surface is removed and never
+/// refers to a different surface after that even if its slot gets reused
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Inspectable, Serialize, Deserialize)]
+pub struct SurfaceId {
+    index: u32,
+    generation: u32,
+}
 
-        self
+impl fmt::Display for SurfaceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
     }
+}
 
-    pub fn run_once(&mut self, world: &mut World) {
-        for system in self.systems.iter_mut() {
-            // change detection is definitely busted
-            system.update_archetype_component_access(world);
-            system.run((), world);
-            system.apply_buffers(world);
-        }
-    }
+struct Surface {
+    schedule: SurfaceSchedule,
+    world: World,
+    /// Decide which of the tagged systems the surface runs, see `Surfaces::push_tagged_system`
+    tags: HashSet<String>,
+    /// World change tick when change ticks were last checked for wrapping around
+    last_tick_check: u32,
+    history: Option<History>,
+    rate: TickRate,
+    /// Steps at `Surfaces::dormant_rate` running only the systems added with `when_dormant`
+    dormant: bool,
+    /// Ticks owed out of `rate.steps`, carried over between steps
+    progress: u32,
+}
 
-    pub fn new() -> Self {
-        Self { systems: vec![] }
-    }
+struct Slot {
+    /// Bumped every time the surface in this slot is removed
+    generation: u32,
+    surface: Option<Surface>,
 }
 
 // Also not Inspectable because Rust magic
 pub struct Surfaces {
-    surfaces: Vec<(SimpleSchedule, World)>,
-    existing_system_ctors:
-        Vec<Box<dyn (Fn() -> Box<dyn System<In = (), Out = ()> + Send + Sync>) + Send + Sync>>,
+    master_seed: u64,
+    slots: Vec<Slot>,
+    /// Indices of empty slots
+    free_slots: Vec<u32>,
+    /// How many surfaces have ever been created, so surfaces get their own rng
+    /// streams even when they reuse a slot
+    created: u64,
+    existing_systems: Vec<(SystemTarget, SurfaceSystem)>,
+    world_inits: Vec<WorldInit>,
+    /// What gets copied when forking a surface
+    state_types: StateTypes,
+    check_invariants: bool,
+    /// Where to write surface state when an invariant fails
+    invariant_dump_dir: Option<PathBuf>,
+    /// Set when an invariant fails, stepping does nothing until `resume`
+    invariant_failure: Option<InvariantReport>,
+    /// How fast dormant surfaces step, whatever their own rate
+    dormant_rate: TickRate,
 }
 
 impl Surfaces {
-    pub fn new() -> Self {
+    /// Every surface's `SurfaceRng` is forked from `master_seed`, so the same seed replays the same simulation
+    pub fn new(master_seed: u64) -> Self {
+        let mut state_types = StateTypes::default();
+        state_types.add_resource::<HexMap<MyTileData>>();
+        state_types.add_resource::<SurfaceRng>();
+        state_types.add_resource::<SurfaceTick>();
         Self {
-            surfaces: vec![],
-            existing_system_ctors: vec![],
+            master_seed,
+            slots: vec![],
+            free_slots: vec![],
+            created: 0,
+            existing_systems: vec![],
+            world_inits: vec![],
+            state_types,
+            check_invariants: cfg!(debug_assertions),
+            invariant_dump_dir: None,
+            invariant_failure: None,
+            dormant_rate: TickRate::every(10),
         }
     }
 
-    pub fn new_surface(&mut self, mut world: World, tilemap: HexMap<MyTileData>) {
+    pub fn new_surface(&mut self, mut world: World, tilemap: HexMap<MyTileData>) -> SurfaceId {
         assert!(!world.contains_resource::<HexMap<MyTileData>>());
         world.insert_resource(tilemap);
+        assert!(!world.contains_resource::<SurfaceRng>());
+        world.insert_resource(SurfaceRng::new(self.master_seed, self.created));
+        self.created += 1;
+        self.init_world(&mut world);
+
+        let mut schedule = SurfaceSchedule::new();
+        for (target, system) in self.existing_systems.iter() {
+            if let SystemTarget::All = target {
+                schedule.add_system(system, &mut world);
+            }
+        }
+
+        self.insert_surface(Surface::new(schedule, world, HashSet::new()))
+    }
+
+    /// Deep copies a surface into a new one with the same tags and systems, so the two can carry on
+    /// differently from there. Systems pushed onto just the one surface with `push_surface_system`
+    /// aren't copied. Copies the resources and components registered with
+    /// `push_state_resource`/`push_state_component`, everything else comes from the world inits.
+    /// The copy's rng is the same so it keeps doing the same thing until something changes it.
+    /// `None` if `id` is stale.
+    pub fn fork(&mut self, id: SurfaceId) -> Option<SurfaceId> {
+        let source = self.surface(id)?;
+        let mut world = World::new();
+        // the world inits expect the map and rng to be there already, and may change them
+        self.state_types.copy_resources(&source.world, &mut world);
+        self.init_world(&mut world);
+        self.state_types.copy_resources(&source.world, &mut world);
+        self.state_types.copy_entities(&source.world, &mut world);
+        world
+            .resource_mut::<Stats>()
+            .copy_samples(source.world.resource::<Stats>());
+
+        let mut schedule = SurfaceSchedule::new();
+        for system in source.schedule.added() {
+            if (self.existing_systems.iter()).any(|(_, existing)| existing.is_same(system)) {
+                schedule.add_system(system, &mut world);
+            }
+        }
+        let surface = Surface {
+            rate: source.rate,
+            dormant: source.dormant,
+            progress: source.progress,
+            ..Surface::new(schedule, world, source.tags.clone())
+        };
+        Some(self.insert_surface(surface))
+    }
 
-        let mut schedule = SimpleSchedule::new();
-        for ctor in self.existing_system_ctors.iter_mut() {
-            schedule.add_system(ctor(), &mut world);
+    /// Writes every surface to `path`. Fails if a surface has a system pushed onto it with
+    /// `push_surface_system`, since that only exists in the code that pushed it.
+    pub fn save(&self, path: &Path) -> Result<(), SaveError> {
+        let mut manifest = vec![];
+        let mut surfaces = vec![];
+        for (id, surface) in self.surfaces() {
+            let systems = (surface.schedule.added())
+                .map(|system| {
+                    (self.existing_systems.iter())
+                        .position(|(_, existing)| existing.is_same(system))
+                        .ok_or(SaveError::SurfaceSystem(id))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut tags = surface.tags.iter().cloned().collect::<Vec<_>>();
+            tags.sort();
+            manifest.push(SurfaceManifest {
+                id,
+                tick: surface.world.resource::<SurfaceTick>().0,
+                tags,
+                rate: surface.rate,
+                dormant: surface.dormant,
+                progress: surface.progress,
+                systems: (surface.schedule.system_names())
+                    .map(|(_, name)| name.into_owned())
+                    .collect(),
+            });
+            surfaces.push(SavedSurface {
+                systems,
+                resources: self.state_types.save_resources(&surface.world)?,
+                entities: self.state_types.save_entities(&surface.world)?,
+                free_entities: (snapshot::free_entities(&surface.world).iter())
+                    .map(|entity| entity.to_bits())
+                    .collect(),
+            });
         }
 
-        self.surfaces.push((schedule, world));
+        SaveFile {
+            version: SAVE_VERSION,
+            master_seed: self.master_seed,
+            created: self.created,
+            manifest,
+            slot_generations: self.slots.iter().map(|slot| slot.generation).collect(),
+            free_slots: self.free_slots.clone(),
+            surfaces,
+        }
+        .write(path)
     }
 
-    // no support for `remove_surface` who knows if we'll need it
+    /// Replaces every surface with the ones saved in `path`, they keep the `SurfaceId`s they had and
+    /// carry on exactly as they would have. The same systems and state types have to have been
+    /// pushed as when the save was made. Nothing changes if loading fails.
+    pub fn load(&mut self, path: &Path) -> Result<(), SaveError> {
+        let save = SaveFile::read(path)?;
+        let mut slots = (save.slot_generations.iter())
+            .map(|&generation| Slot {
+                generation,
+                surface: None,
+            })
+            .collect::<Vec<_>>();
+        for (manifest, saved) in save.manifest.iter().zip(save.surfaces.iter()) {
+            let surface = self.load_surface(manifest, saved)?;
+            let slot = (slots.get_mut(manifest.id.index as usize))
+                .filter(|slot| slot.generation == manifest.id.generation && slot.surface.is_none())
+                .ok_or_else(|| {
+                    SaveError::Mismatch(format!("no slot for surface {}", manifest.id))
+                })?;
+            slot.surface = Some(surface);
+        }
+
+        self.master_seed = save.master_seed;
+        self.created = save.created;
+        self.slots = slots;
+        self.free_slots = save.free_slots;
+        self.invariant_failure = None;
+        Ok(())
+    }
+
+    fn load_surface(
+        &self,
+        manifest: &SurfaceManifest,
+        saved: &SavedSurface,
+    ) -> Result<Surface, SaveError> {
+        let mut world = self.load_world(
+            manifest.id,
+            &saved.resources,
+            &saved.entities,
+            &saved.free_entities,
+        )?;
+
+        let mut schedule = SurfaceSchedule::new();
+        for &system in saved.systems.iter() {
+            let (_, system) = self.existing_systems.get(system).ok_or_else(|| {
+                SaveError::Mismatch(format!("surface {} has unknown systems", manifest.id))
+            })?;
+            schedule.add_system(system, &mut world);
+        }
+        if !(schedule.system_names())
+            .map(|(_, name)| name)
+            .eq(manifest.systems.iter().map(Cow::from))
+        {
+            return Err(SaveError::Mismatch(format!(
+                "surface {} has different systems",
+                manifest.id
+            )));
+        }
+
+        Ok(Surface {
+            rate: manifest.rate,
+            dormant: manifest.dormant,
+            progress: manifest.progress,
+            ..Surface::new(schedule, world, manifest.tags.iter().cloned().collect())
+        })
+    }
+
+    /// A new world with the surface's saved state and whatever the world inits add
+    fn load_world(
+        &self,
+        id: SurfaceId,
+        resources: &SavedValues,
+        entities: &[SavedEntity],
+        free_entities: &[u64],
+    ) -> Result<World, SaveError> {
+        let mut world = World::new();
+        // the world inits expect the map and rng to be there already, and may change them
+        self.state_types.load_resources(resources, &mut world)?;
+        if !world.contains_resource::<HexMap<MyTileData>>()
+            || !world.contains_resource::<SurfaceRng>()
+        {
+            return Err(SaveError::Mismatch(format!(
+                "surface {id} has no map or rng"
+            )));
+        }
+        self.init_world(&mut world);
+        self.state_types.load_resources(resources, &mut world)?;
+        self.state_types.load_entities(entities, &mut world)?;
+        let free_entities = (free_entities.iter())
+            .map(|&entity| Entity::from_bits(entity))
+            .collect::<Vec<_>>();
+        if !snapshot::free_like(&free_entities, &mut world) {
+            return Err(SaveError::Mismatch(format!(
+                "surface {id} has entities that are also free"
+            )));
+        }
+        Ok(world)
+    }
+
+    /// Every tile that's different between the two surfaces' maps, `None` if either id is stale or
+    /// the maps aren't the same size
+    pub fn diff_tiles(&self, a: SurfaceId, b: SurfaceId) -> Option<Vec<TileDiff>> {
+        snapshot::diff_tiles(
+            self.get(a)?.resource::<HexMap<MyTileData>>(),
+            self.get(b)?.resource::<HexMap<MyTileData>>(),
+        )
+    }
 
-    pub fn push_system<Params>(
+    /// Inserts the resources every surface has, `world` needs a map and rng already
+    fn init_world(&self, world: &mut World) {
+        world.init_resource::<SurfaceTick>();
+        world.init_resource::<Invariants>();
+        world.init_resource::<Stats>();
+
+        for init in self.world_inits.iter() {
+            init(world);
+        }
+    }
+
+    fn insert_surface(&mut self, surface: Surface) -> SurfaceId {
+        let index = self.free_slots.pop().unwrap_or_else(|| {
+            self.slots.push(Slot {
+                generation: 0,
+                surface: None,
+            });
+            self.slots.len() as u32 - 1
+        });
+        let slot = &mut self.slots[index as usize];
+        slot.surface = Some(surface);
+        SurfaceId {
+            index,
+            generation: slot.generation,
+        }
+    }
+
+    /// Gives back the surface's world, `None` if `id` is stale
+    pub fn remove_surface(&mut self, id: SurfaceId) -> Option<World> {
+        let slot = self.slot_mut(id)?;
+        let world = slot.surface.take()?.world;
+        slot.generation += 1;
+        self.free_slots.push(id.index);
+        Some(world)
+    }
+
+    pub fn contains(&self, id: SurfaceId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// `None` if `id` is stale
+    pub fn get(&self, id: SurfaceId) -> Option<&World> {
+        self.surface(id).map(|surface| &surface.world)
+    }
+
+    /// `None` if `id` is stale
+    pub fn get_mut(&mut self, id: SurfaceId) -> Option<&mut World> {
+        self.surface_mut(id).map(|surface| &mut surface.world)
+    }
+
+    fn surface(&self, id: SurfaceId) -> Option<&Surface> {
+        let slot = self.slots.get(id.index as usize)?;
+        match slot.generation == id.generation {
+            true => slot.surface.as_ref(),
+            false => None,
+        }
+    }
+
+    fn surface_mut(&mut self, id: SurfaceId) -> Option<&mut Surface> {
+        self.slot_mut(id)?.surface.as_mut()
+    }
+
+    fn slot_mut(&mut self, id: SurfaceId) -> Option<&mut Slot> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation && slot.surface.is_some())
+    }
+
+    /// Every live surface, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (SurfaceId, &World)> {
+        self.surfaces().map(|(id, surface)| (id, &surface.world))
+    }
+
+    /// Every live surface, in slot order
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (SurfaceId, &mut World)> {
+        self.surfaces_mut()
+            .map(|(id, surface)| (id, &mut surface.world))
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = SurfaceId> + '_ {
+        self.iter().map(|(id, _)| id)
+    }
+
+    /// Live surfaces with `tag`
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = SurfaceId> + 'a {
+        self.surfaces()
+            .filter(move |(_, surface)| surface.tags.contains(tag))
+            .map(|(id, _)| id)
+    }
+
+    /// `None` if `id` is stale
+    pub fn tags(&self, id: SurfaceId) -> Option<impl Iterator<Item = &str>> {
+        Some(self.surface(id)?.tags.iter().map(String::as_str))
+    }
+
+    /// Adds every system pushed with `push_tagged_system(tag, ..)` to the surface, tags can't be
+    /// taken off again. Does nothing if `id` is stale or the surface already has `tag`.
+    pub fn add_tag(&mut self, id: SurfaceId, tag: &str) -> &mut Self {
+        // not `surface_mut` so `existing_systems` can still be borrowed
+        let Some(surface) = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.surface.as_mut())
+        else {
+            return self;
+        };
+        if surface.tags.insert(tag.to_owned()) {
+            for (target, system) in self.existing_systems.iter() {
+                if matches!(target, SystemTarget::Tagged(target) if target == tag) {
+                    surface.schedule.add_system(system, &mut surface.world);
+                }
+            }
+        }
+
+        self
+    }
+
+    /// Names of the systems the surface runs in the order it runs them, `None` if `id` is stale
+    pub fn systems(
+        &self,
+        id: SurfaceId,
+    ) -> Option<impl Iterator<Item = (SurfaceStage, Cow<'static, str>)> + '_> {
+        Some(self.surface(id)?.schedule.system_names())
+    }
+
+    fn surfaces(&self) -> impl Iterator<Item = (SurfaceId, &Surface)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = SurfaceId {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            Some((id, slot.surface.as_ref()?))
+        })
+    }
+
+    fn surfaces_mut(&mut self) -> impl Iterator<Item = (SurfaceId, &mut Surface)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let id = SurfaceId {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+                Some((id, slot.surface.as_mut()?))
+            })
+    }
+
+    pub fn master_seed(&self) -> u64 {
+        self.master_seed
+    }
+
+    /// Adds `system` to every existing and future surface
+    pub fn push_system<Params>(&mut self, system: impl IntoSurfaceSystem<Params>) -> &mut Self {
+        self.push_targeted_system(SystemTarget::All, system.into_surface_system())
+    }
+
+    /// Adds `system` to every existing and future surface with `tag`, see `add_tag`
+    pub fn push_tagged_system<Params>(
         &mut self,
-        system: impl IntoSystem<(), (), Params> + Clone + Send + Sync + 'static,
+        tag: &str,
+        system: impl IntoSurfaceSystem<Params>,
     ) -> &mut Self {
-        let system_ctor: Box<
-            dyn Fn() -> Box<dyn System<In = (), Out = ()> + Send + Sync> + Send + Sync,
-        > = Box::new(move || Box::new(IntoSystem::into_system(system.clone())));
+        self.push_targeted_system(
+            SystemTarget::Tagged(tag.to_owned()),
+            system.into_surface_system(),
+        )
+    }
 
-        for (schedule, world) in self.surfaces.iter_mut() {
-            schedule.add_system(system_ctor(), world);
+    /// Adds `system` to just the one surface, does nothing if `id` is stale
+    pub fn push_surface_system<Params>(
+        &mut self,
+        id: SurfaceId,
+        system: impl IntoSurfaceSystem<Params>,
+    ) -> &mut Self {
+        if let Some(surface) = self.surface_mut(id) {
+            surface
+                .schedule
+                .add_system(&system.into_surface_system(), &mut surface.world);
         }
-        self.existing_system_ctors.push(system_ctor);
 
         self
     }
 
+    fn push_targeted_system(&mut self, target: SystemTarget, system: SurfaceSystem) -> &mut Self {
+        for (_, surface) in self.surfaces_mut() {
+            let wanted = match &target {
+                SystemTarget::All => true,
+                SystemTarget::Tagged(tag) => surface.tags.contains(tag),
+            };
+            if wanted {
+                surface.schedule.add_system(&system, &mut surface.world);
+            }
+        }
+        self.existing_systems.push((target, system));
+
+        self
+    }
+
+    /// Runs `init` on every existing and future surface world, for inserting the resources that
+    /// surface systems expect to exist. Surfaces created later run it before their systems are added.
+    pub fn push_world_init(
+        &mut self,
+        init: impl Fn(&mut World) + Send + Sync + 'static,
+    ) -> &mut Self {
+        for (_, world) in self.iter_mut() {
+            init(world);
+        }
+        self.world_inits.push(Box::new(init));
+
+        self
+    }
+
+    /// Registers an invariant on every existing and future surface
+    pub fn push_invariant(
+        &mut self,
+        name: &str,
+        check: impl Fn(&mut World) -> Result<(), Violation> + Send + Sync + 'static,
+    ) -> &mut Self {
+        let name = name.to_owned();
+        let check: invariants::InvariantCheck = Arc::new(check);
+        self.push_world_init(move |world| {
+            world.resource_mut::<Invariants>().0.push(Invariant {
+                name: name.clone(),
+                check: check.clone(),
+            });
+        })
+    }
+
+    /// Registers a metric on every existing and future surface, `metric` shouldn't change anything
+    /// and its value gets recorded every `Stats::sample_every` ticks
+    pub fn push_metric<Params>(
+        &mut self,
+        name: &str,
+        metric: impl IntoSystem<(), f32, Params> + Clone + Send + Sync + 'static,
+    ) -> &mut Self {
+        let name = name.to_owned();
+        self.push_world_init(move |world| {
+            let mut metric: stats::Metric = Box::new(IntoSystem::into_system(metric.clone()));
+            metric.initialize(world);
+            world
+                .resource_mut::<Stats>()
+                .add_metric(name.clone(), metric);
+        })
+    }
+
+    /// Marks `T` as part of a surface's state, so it's copied over when forking a surface and saved
+    pub fn push_state_resource<T: Resource + Clone + Serialize + DeserializeOwned>(
+        &mut self,
+    ) -> &mut Self {
+        self.state_types.add_resource::<T>();
+        self
+    }
+
+    /// Marks `T` as part of a surface's state, so entities keep it when forking a surface and saving
+    pub fn push_state_component<T: Component + Clone + Serialize + DeserializeOwned>(
+        &mut self,
+    ) -> &mut Self {
+        self.state_types.add_component::<T>();
+        self
+    }
+
+    /// On by default in debug builds. Failures pause the simulation, and dump the failing
+    /// surface's state into `dump_dir` if there is one.
+    pub fn set_invariant_checks(&mut self, enabled: bool, dump_dir: Option<PathBuf>) {
+        self.check_invariants = enabled;
+        self.invariant_dump_dir = dump_dir;
+    }
+
+    /// The failure the simulation is paused on, if any
+    pub fn invariant_failure(&self) -> Option<&InvariantReport> {
+        self.invariant_failure.as_ref()
+    }
+
+    /// Carries on stepping after an invariant failure
+    pub fn resume(&mut self) {
+        self.invariant_failure = None;
+    }
+
+    /// `None` if `id` is stale
+    pub fn tick_rate(&self, id: SurfaceId) -> Option<TickRate> {
+        self.surface(id).map(|surface| surface.rate)
+    }
+
+    /// The rate the surface steps at while it's awake, see `TickRate`
+    pub fn set_tick_rate(&mut self, id: SurfaceId, rate: TickRate) {
+        if let Some(surface) = self.surface_mut(id) {
+            surface.rate = rate;
+            surface.progress = 0;
+        }
+    }
+
+    /// `false` if `id` is stale
+    pub fn is_dormant(&self, id: SurfaceId) -> bool {
+        self.surface(id).is_some_and(|surface| surface.dormant)
+    }
+
+    /// Dormant surfaces step at `dormant_rate` and only run the systems added with
+    /// `SurfaceSystem::when_dormant`, for surfaces nobody's looking at. Waking a surface puts it
+    /// back on its own rate.
+    pub fn set_dormant(&mut self, id: SurfaceId, dormant: bool) {
+        if let Some(surface) = self.surface_mut(id) {
+            if surface.dormant != dormant {
+                surface.dormant = dormant;
+                surface.progress = 0;
+            }
+        }
+    }
+
+    pub fn dormant_rate(&self) -> TickRate {
+        self.dormant_rate
+    }
+
+    pub fn set_dormant_rate(&mut self, rate: TickRate) {
+        self.dormant_rate = rate;
+    }
+
+    /// Starts or stops keeping the surface's recent past so it can be scrubbed through and
+    /// rewound, see `History`. Starting over throws away what was recorded.
+    pub fn set_history(&mut self, id: SurfaceId, settings: Option<HistorySettings>) {
+        if let Some(surface) = self.surface_mut(id) {
+            surface.history = settings.map(|settings| History::new(settings, &surface.world));
+        }
+    }
+
+    /// `None` if `id` is stale or isn't recording history
+    pub fn history(&self, id: SurfaceId) -> Option<&History> {
+        self.surface(id)?.history.as_ref()
+    }
+
+    /// Puts the surface back how it was at the start of `tick`, by loading the keyframe before it
+    /// and stepping on from there. What was recorded after `tick` is thrown away, the steps taken
+    /// to get back to it get recorded again, stopping short if an invariant fails on the way.
+    /// `Ok(false)` if `id` is stale or `tick` isn't in its history, nothing changes if loading the
+    /// keyframe fails.
+    pub fn rewind_to(&mut self, id: SurfaceId, tick: u64) -> Result<bool, SaveError> {
+        let Some(surface) = self.surface(id) else {
+            return Ok(false);
+        };
+        let Some(keyframe) = (surface.history.as_ref())
+            .filter(|history| history.contains(tick))
+            .and_then(|history| history.keyframe_before(tick))
+        else {
+            return Ok(false);
+        };
+        let mut world = self.load_world(
+            id,
+            &keyframe.resources,
+            &keyframe.entities,
+            &keyframe.free_entities,
+        )?;
+        let mut stats = world.resource_mut::<Stats>();
+        stats.copy_samples(surface.world.resource::<Stats>());
+        stats.forget_from(keyframe.tick);
+        let schedule = surface.schedule.fork(&mut world);
+
+        let surface = (self.slots[id.index as usize].surface.as_mut()).unwrap();
+        let history = surface.history.as_mut().unwrap();
+        let keyframe = history.truncate_to_keyframe(tick).unwrap();
+        history.reset_reader(&world);
+        surface.world = world;
+        surface.schedule = schedule;
+        surface.last_tick_check = 0;
+
+        for _ in keyframe.tick..tick {
+            let report = surface.step(
+                id,
+                self.check_invariants,
+                self.invariant_dump_dir.as_deref(),
+                &self.state_types,
+            );
+            // stop where it failed, the same as `simulate_step`
+            if let Some(report) = report {
+                self.invariant_failure.get_or_insert(report);
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Takes every `TileChanged` the surface has sent since the last drain, oldest first.
+    /// Nothing if `surface` is stale.
+    pub fn drain_tile_changes(
+        &mut self,
+        surface: SurfaceId,
+    ) -> impl Iterator<Item = TileChanged> + '_ {
+        self.get_mut(surface).into_iter().flat_map(|world| {
+            world
+                .resource_mut::<Events<TileChanged>>()
+                .into_inner()
+                .drain()
+        })
+    }
+
+    /// Steps every surface as many times as its `TickRate` says, surfaces share nothing so
+    /// they're stepped in parallel on the `ComputeTaskPool`
     pub fn simulate_step(&mut self) {
-        for (schedule, surface) in self.surfaces.iter_mut() {
-            schedule.run_once(surface);
+        if self.invariant_failure.is_some() {
+            return;
+        }
+        let check_invariants = self.check_invariants;
+        let dump_dir = self.invariant_dump_dir.as_deref();
+        let state_types = &self.state_types;
+        let dormant_rate = self.dormant_rate;
+        let reports = ComputeTaskPool::init(TaskPool::default).scope(|scope| {
+            for (index, slot) in self.slots.iter_mut().enumerate() {
+                let id = SurfaceId {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+                if let Some(surface) = &mut slot.surface {
+                    scope.spawn(async move {
+                        for _ in 0..surface.ticks_due(dormant_rate) {
+                            let report = surface.step(id, check_invariants, dump_dir, state_types);
+                            if report.is_some() {
+                                return report;
+                            }
+                        }
+                        None
+                    });
+                }
+            }
+        });
+        // the other surfaces still got their ticks for this step, reports
+        // come back in slot order so the same one gets picked every time
+        if let Some(report) = reports.into_iter().flatten().next() {
+            self.invariant_failure = Some(report);
+        }
+    }
+}
+
+impl Surface {
+    fn new(schedule: SurfaceSchedule, world: World, tags: HashSet<String>) -> Self {
+        Self {
+            schedule,
+            world,
+            tags,
+            last_tick_check: 0,
+            history: None,
+            rate: TickRate::NORMAL,
+            dormant: false,
+            progress: 0,
         }
     }
+
+    /// How many times to step for one `Surfaces::simulate_step`
+    fn ticks_due(&mut self, dormant_rate: TickRate) -> u32 {
+        let rate = match self.dormant {
+            true => dormant_rate,
+            false => self.rate,
+        };
+        let steps = rate.steps.max(1);
+        self.progress += rate.ticks;
+        let ticks = self.progress / steps;
+        self.progress %= steps;
+        ticks
+    }
+
+    /// Returns the invariants that failed, if checking them
+    fn step(
+        &mut self,
+        id: SurfaceId,
+        check_invariants: bool,
+        dump_dir: Option<&Path>,
+        state_types: &StateTypes,
+    ) -> Option<InvariantReport> {
+        let Self {
+            schedule,
+            world: surface,
+            last_tick_check,
+            history,
+            dormant,
+            ..
+        } = self;
+        let tick = surface.resource::<SurfaceTick>().0;
+        if let Some(history) = history {
+            history.record_keyframe(tick, surface, state_types);
+        }
+        schedule.run_once(surface, *dormant);
+        if let Some(history) = history {
+            history.record_tick(surface);
+        }
+
+        let report = match check_invariants {
+            true => invariants::check_surface(id, tick, surface),
+            false => None,
+        };
+        if let Some(report) = &report {
+            invariants::report(report, surface, dump_dir);
+        }
+        stats::sample(tick, surface);
+        surface.resource_mut::<SurfaceTick>().0 += 1;
+        // so copies and saves made between steps hand out the same ids next
+        snapshot::sort_free_entities(surface);
+
+        // the same upkeep `App::update` does for the main world so change detection works
+        let change_tick = surface.change_tick();
+        if change_tick.wrapping_sub(*last_tick_check) >= CHECK_TICK_THRESHOLD {
+            schedule.check_change_ticks(change_tick);
+            stats::check_change_ticks(surface, change_tick);
+            surface.check_change_ticks();
+            *last_tick_check = change_tick;
+        }
+        surface.clear_trackers();
+
+        report
+    }
 }
 
+/// How many times a surface's schedule has run, bumped after each step
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Inspectable, Serialize, Deserialize)]
+pub struct SurfaceTick(pub u64);
+
+/// How fast a surface steps, `ticks` ticks every `steps` calls to `Surfaces::simulate_step`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspectable, Serialize, Deserialize)]
+pub struct TickRate {
+    pub ticks: u32,
+    pub steps: u32,
+}
+
+impl TickRate {
+    pub const NORMAL: Self = Self { ticks: 1, steps: 1 };
+    pub const PAUSED: Self = Self { ticks: 0, steps: 1 };
+
+    /// A tick every `steps` steps, for slow surfaces
+    pub fn every(steps: u32) -> Self {
+        Self { ticks: 1, steps }
+    }
+
+    /// `ticks` ticks every step, for fast surfaces
+    pub fn times(ticks: u32) -> Self {
+        Self { ticks, steps: 1 }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.ticks == 0
+    }
+}
+
+/// The surface that's drawn and that user actions apply to. Can go stale if the surface is
+/// removed, main world systems should check with `Surfaces::get` rather than assume it's there.
 #[derive(Debug, Inspectable)]
-pub struct SelectedSurface(pub usize);
+pub struct SelectedSurface(pub SurfaceId);
 
 // Not Inspectable due to Rust magic
 #[derive(SystemParam)]
 pub struct CurrentHexMap<'w, 's> {
     selected: Res<'w, SelectedSurface>,
     surfaces: Res<'w, Surfaces>,
+    timeline: Res<'w, Timeline>,
     #[system_param(ignore)]
     _p: PhantomData<&'s ()>,
 }
 
 impl CurrentHexMap<'_, '_> {
-    pub fn hexmap(&self) -> &HexMap<MyTileData> {
-        self.surfaces.surfaces[self.selected.0]
-            .1
-            .get_resource()
-            .unwrap()
+    /// `None` if the selected surface has been removed. The past map while the History window is
+    /// looking at one.
+    pub fn hexmap(&self) -> Option<&HexMap<MyTileData>> {
+        if let Some(map) = self.timeline.map(self.selected.0) {
+            return Some(map);
+        }
+        self.surfaces
+            .get(self.selected.0)
+            .map(|world| world.resource())
+    }
+}
+
+/// Like `CurrentHexMap` but for changing the selected surface's map. Edits go to the live map even
+/// while the History window shows a past one, and get reported as `TileChanged` at the end of the
+/// surface's next tick.
+// Not Inspectable due to Rust magic
+#[derive(SystemParam)]
+pub struct CurrentHexMapMut<'w, 's> {
+    selected: ResMut<'w, SelectedSurface>,
+    surfaces: ResMut<'w, Surfaces>,
+    #[system_param(ignore)]
+    _p: PhantomData<&'s ()>,
+}
+
+impl CurrentHexMapMut<'_, '_> {
+    /// `None` if the selected surface has been removed
+    pub fn hexmap(&self) -> Option<&HexMap<MyTileData>> {
+        self.surfaces
+            .get(self.selected.0)
+            .map(|world| world.resource())
+    }
+
+    /// `None` if the selected surface has been removed. Gets the whole map redrawn, as the surface
+    /// may not tick to report the edits while it's paused or the History window is open.
+    pub fn hexmap_mut(&mut self) -> Option<Mut<'_, HexMap<MyTileData>>> {
+        let world = self.surfaces.get_mut(self.selected.0)?;
+        self.selected.set_changed();
+        Some(world.resource_mut())
+    }
+}
+
+/// The selected surface's world, for main world systems that read or change anything on it
+// Not Inspectable due to Rust magic
+#[derive(SystemParam)]
+pub struct CurrentSurface<'w, 's> {
+    selected: Res<'w, SelectedSurface>,
+    surfaces: ResMut<'w, Surfaces>,
+    #[system_param(ignore)]
+    _p: PhantomData<&'s ()>,
+}
+
+impl CurrentSurface<'_, '_> {
+    pub fn id(&self) -> SurfaceId {
+        self.selected.0
+    }
+
+    /// `None` if the selected surface has been removed
+    pub fn world(&self) -> Option<&World> {
+        self.surfaces.get(self.selected.0)
+    }
+
+    /// `None` if the selected surface has been removed
+    pub fn world_mut(&mut self) -> Option<&mut World> {
+        self.surfaces.get_mut(self.selected.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geology::{GeologyEvent, GeologyEvents},
+        hexmap::HexPos,
+        settlements::Settlement,
+        simulation::{self, TileKind},
+        wildfire::Ignitions,
+    };
+
+    const SEED: u64 = 1234;
+
+    /// Tick, map, rng and every entity's saved components, sorted by entity
+    type State = (u64, HexMap<MyTileData>, SurfaceRng, Vec<(u64, SavedValues)>);
+
+    fn state(surfaces: &Surfaces, id: SurfaceId) -> State {
+        let world = surfaces.get(id).unwrap();
+        let mut entities = (surfaces.state_types.save_entities(world).unwrap())
+            .into_iter()
+            .map(|saved| (saved.entity, saved.components))
+            .collect::<Vec<_>>();
+        entities.sort_by_key(|&(entity, _)| entity);
+        (
+            world.resource::<SurfaceTick>().0,
+            world.resource::<HexMap<MyTileData>>().clone(),
+            world.resource::<SurfaceRng>().clone(),
+            entities,
+        )
+    }
+
+    fn assert_same(a: &State, b: &State) {
+        assert_eq!(a.0, b.0, "ticks differ");
+        assert_eq!(
+            snapshot::diff_tiles(&a.1, &b.1).map(|diffs| diffs.len()),
+            Some(0)
+        );
+        assert_eq!(a.2, b.2, "rngs differ");
+        assert_eq!(a.3, b.3, "entities differ");
+    }
+
+    fn step(surfaces: &mut Surfaces, steps: u32) {
+        for _ in 0..steps {
+            surfaces.simulate_step();
+        }
+        assert!(surfaces.invariant_failure().is_none());
+    }
+
+    /// The starting surfaces plus two settlements, an eruption and a fire, so the systems that only
+    /// do anything once those exist (trade, lava, fire spreading) get covered too
+    fn busy_surfaces() -> (Surfaces, SurfaceId) {
+        let (mut surfaces, id) = simulation::new_surfaces(SEED);
+        let world = surfaces.get_mut(id).unwrap();
+        let map = world.resource::<HexMap<MyTileData>>();
+        let islands = (map.positions())
+            .filter(|&pos| map.get(pos).kind == TileKind::Rock)
+            .collect::<Vec<_>>();
+        let center = HexPos {
+            q: map.width() as i32 / 2,
+            r: map.height() as i32 / 2,
+        };
+        for &pos in [islands[0], islands[islands.len() - 1]].iter() {
+            world
+                .spawn()
+                .insert_bundle((pos, Settlement { population: 50.0 }));
+        }
+        (world.resource_mut::<GeologyEvents>().0).push(GeologyEvent::Erupt {
+            pos: center,
+            ticks: 200,
+        });
+        (world.resource_mut::<Ignitions>().0).push(islands[islands.len() / 2]);
+        (surfaces, id)
+    }
+
+    /// Whether some entity in `state` has a component whose type name ends with `name`
+    fn has_component(state: &State, name: &str) -> bool {
+        (state.3.iter())
+            .flat_map(|(_, components)| components)
+            .any(|(component, _)| component.ends_with(name))
+    }
+
+    #[test]
+    fn loading_a_save_carries_on_the_same() {
+        let path = std::env::temp_dir().join(format!("hexy_test_save_{}.ron", std::process::id()));
+        let (mut surfaces, id) = busy_surfaces();
+        step(&mut surfaces, 1000);
+        assert!(!snapshot::free_entities(surfaces.get(id).unwrap()).is_empty());
+        surfaces.save(&path).unwrap();
+        step(&mut surfaces, 1000);
+        let expected = state(&surfaces, id);
+        assert!(has_component(&expected, "::Settlement"));
+        assert!(has_component(&expected, "::Volcano"));
+        assert!(has_component(&expected, "::Producer"));
+
+        let loaded = surfaces.load(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+        step(&mut surfaces, 1000);
+        assert_same(&state(&surfaces, id), &expected);
+    }
+
+    #[test]
+    fn forks_carry_on_the_same() {
+        let (mut surfaces, id) = busy_surfaces();
+        step(&mut surfaces, 1000);
+        // so the fork has to hand out reused ids the same way
+        assert!(!snapshot::free_entities(surfaces.get(id).unwrap()).is_empty());
+        let fork = surfaces.fork(id).unwrap();
+        step(&mut surfaces, 1000);
+        assert_same(&state(&surfaces, fork), &state(&surfaces, id));
+    }
+
+    #[test]
+    fn other_surfaces_dont_change_a_surface() {
+        let (mut alone, id) = busy_surfaces();
+        let (mut together, together_id) = busy_surfaces();
+        step(&mut together, 500);
+        together.fork(together_id).unwrap();
+        step(&mut alone, 1500);
+        step(&mut together, 1000);
+        assert_same(&state(&together, together_id), &state(&alone, id));
+    }
+
+    #[test]
+    fn removed_ids_stay_stale() {
+        let (mut surfaces, old) = simulation::new_surfaces(SEED);
+        let other = surfaces.fork(old).unwrap();
+        assert!(surfaces.remove_surface(old).is_some());
+        assert!(surfaces.get(old).is_none());
+        assert!(surfaces.get_mut(old).is_none());
+        assert!(surfaces.tick_rate(old).is_none());
+        assert_eq!(surfaces.drain_tile_changes(old).count(), 0);
+        assert!(surfaces.remove_surface(old).is_none());
+
+        // the freed slot gets reused for the next surface, under a new generation
+        let new = surfaces.fork(other).unwrap();
+        assert_eq!(new.index, old.index);
+        assert_ne!(new.generation, old.generation);
+        assert!(!surfaces.contains(old));
+        assert!(surfaces.get(old).is_none());
+        assert!(surfaces.tick_rate(old).is_none());
+
+        // nothing done through the old id reaches the new surface
+        surfaces.set_tick_rate(old, TickRate::PAUSED);
+        surfaces.set_dormant(old, true);
+        assert_eq!(surfaces.tick_rate(new), Some(TickRate::NORMAL));
+        assert!(!surfaces.is_dormant(new));
+        step(&mut surfaces, 20);
+        assert_eq!(surfaces.drain_tile_changes(old).count(), 0);
+        assert!(surfaces.drain_tile_changes(new).count() > 0);
+        assert!(surfaces.remove_surface(old).is_none());
+        assert!(surfaces.contains(new));
+        assert_eq!(surfaces.ids().collect::<Vec<_>>(), [new, other]);
+    }
+
+    /// How many times `count_runs` ran on a surface
+    #[derive(Default)]
+    struct Runs(u32);
+
+    fn count_runs(mut runs: ResMut<Runs>) {
+        runs.0 += 1;
+    }
+
+    fn runs(surfaces: &Surfaces, id: SurfaceId) -> u32 {
+        surfaces.get(id).unwrap().resource::<Runs>().0
+    }
+
+    #[test]
+    fn tagged_systems_only_run_on_tagged_surfaces() {
+        let (mut surfaces, tagged) = simulation::new_surfaces(SEED);
+        let untagged = surfaces.fork(tagged).unwrap();
+        let tagged_later = surfaces.fork(tagged).unwrap();
+        surfaces
+            .add_tag(tagged, "watched")
+            .push_world_init(|world| world.init_resource::<Runs>())
+            .push_tagged_system("watched", count_runs);
+        let tagged_fork = surfaces.fork(tagged).unwrap();
+        step(&mut surfaces, 3);
+        assert_eq!(runs(&surfaces, tagged), 3);
+        assert_eq!(runs(&surfaces, tagged_fork), 3);
+        assert_eq!(runs(&surfaces, untagged), 0);
+        assert_eq!(runs(&surfaces, tagged_later), 0);
+
+        surfaces.add_tag(tagged_later, "watched");
+        step(&mut surfaces, 2);
+        assert_eq!(runs(&surfaces, tagged_later), 2);
+        assert_eq!(runs(&surfaces, untagged), 0);
+        assert_eq!(
+            surfaces.tagged("watched").collect::<HashSet<_>>(),
+            HashSet::from([tagged, tagged_fork, tagged_later])
+        );
+    }
+
+    #[test]
+    fn surface_systems_only_run_on_their_surface() {
+        let (mut surfaces, id) = simulation::new_surfaces(SEED);
+        let sibling = surfaces.fork(id).unwrap();
+        surfaces
+            .push_world_init(|world| world.init_resource::<Runs>())
+            .push_surface_system(id, count_runs);
+        let fork = surfaces.fork(id).unwrap();
+        step(&mut surfaces, 3);
+        assert_eq!(runs(&surfaces, id), 3);
+        assert_eq!(runs(&surfaces, sibling), 0);
+        assert_eq!(runs(&surfaces, fork), 0);
+    }
+
+    /// Ticks due over `steps` calls to `Surfaces::simulate_step`
+    fn ticks_over(surface: &mut Surface, steps: u32, dormant_rate: TickRate) -> u32 {
+        (0..steps).map(|_| surface.ticks_due(dormant_rate)).sum()
+    }
+
+    #[test]
+    fn tick_rates_carry_over_between_steps() {
+        let mut surface = Surface::new(SurfaceSchedule::new(), World::new(), HashSet::new());
+        surface.rate = TickRate { ticks: 3, steps: 2 };
+        let due = (0..10)
+            .map(|_| surface.ticks_due(TickRate::PAUSED))
+            .collect::<Vec<_>>();
+        assert_eq!(due, [1, 2, 1, 2, 1, 2, 1, 2, 1, 2]);
+        assert_eq!(due.iter().sum::<u32>(), 15);
+
+        surface.rate = TickRate::PAUSED;
+        assert_eq!(ticks_over(&mut surface, 10, TickRate::NORMAL), 0);
+    }
+
+    #[test]
+    fn dormant_surfaces_tick_at_the_dormant_rate() {
+        let mut surface = Surface::new(SurfaceSchedule::new(), World::new(), HashSet::new());
+        surface.dormant = true;
+        assert_eq!(ticks_over(&mut surface, 30, TickRate::every(10)), 3);
+        assert_eq!(ticks_over(&mut surface, 30, TickRate::PAUSED), 0);
+
+        surface.dormant = false;
+        assert_eq!(ticks_over(&mut surface, 30, TickRate::every(10)), 30);
+    }
+
+    #[derive(Component)]
+    struct Watched(u32);
+
+    /// `(added, changed, removed)` `Watched` components each `watch` run saw, in the order they ran
+    #[derive(Default)]
+    struct Seen(Vec<(usize, usize, usize)>);
+
+    fn watch(
+        added: Query<(), Added<Watched>>,
+        changed: Query<(), Changed<Watched>>,
+        removed: RemovedComponents<Watched>,
+        mut seen: ResMut<Seen>,
+    ) {
+        seen.0.push((
+            added.iter().count(),
+            changed.iter().count(),
+            removed.iter().count(),
+        ));
+    }
+
+    /// Spawns a `Watched` on tick 0, changes it on tick 2 and removes it on tick 3
+    fn write(
+        tick: Res<SurfaceTick>,
+        mut cmds: Commands,
+        mut watched: Query<(Entity, &mut Watched)>,
+    ) {
+        match tick.0 {
+            0 => {
+                cmds.spawn().insert(Watched(0));
+            }
+            2 => watched.single_mut().1 .0 += 1,
+            3 => {
+                cmds.entity(watched.single().0).remove::<Watched>();
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn changes_are_seen_until_the_next_step() {
+        let (mut surfaces, id) = simulation::new_surfaces(SEED);
+        surfaces
+            .push_world_init(|world| world.init_resource::<Seen>())
+            .push_surface_system(id, SurfaceSystem::new(watch).in_stage(SurfaceStage::PreSim))
+            .push_surface_system(id, write)
+            .push_surface_system(
+                id,
+                SurfaceSystem::new(watch).in_stage(SurfaceStage::PostSim),
+            );
+        step(&mut surfaces, 5);
+        // before and after `write` on each tick, the one before sees what the last tick wrote
+        // and removals are forgotten once the step they happened in is over
+        assert_eq!(
+            surfaces.get(id).unwrap().resource::<Seen>().0,
+            [
+                (0, 0, 0),
+                (1, 1, 0),
+                (1, 1, 0),
+                (0, 0, 0),
+                (0, 0, 0),
+                (0, 1, 0),
+                (0, 1, 0),
+                (0, 0, 1),
+                (0, 0, 0),
+                (0, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewinding_matches_a_fresh_run() {
+        let (mut fresh, fresh_id) = simulation::new_surfaces(SEED);
+        step(&mut fresh, 250);
+        let at_250 = state(&fresh, fresh_id);
+        step(&mut fresh, 100);
+        let at_350 = state(&fresh, fresh_id);
+
+        let (mut surfaces, id) = simulation::new_surfaces(SEED);
+        surfaces.set_history(id, Some(HistorySettings::default()));
+        step(&mut surfaces, 400);
+        assert!(surfaces.rewind_to(id, 250).unwrap());
+        assert!(surfaces.invariant_failure().is_none());
+        assert_same(&state(&surfaces, id), &at_250);
+        step(&mut surfaces, 100);
+        assert_same(&state(&surfaces, id), &at_350);
+
+        // what came after the rewind is gone
+        assert_eq!(surfaces.history(id).unwrap().range(), Some((0, 350)));
+        assert!(!surfaces.rewind_to(id, 351).unwrap());
+    }
+
+    #[test]
+    fn history_maps_match_the_live_map() {
+        let (mut surfaces, id) = simulation::new_surfaces(SEED);
+        surfaces.set_history(id, Some(HistorySettings::default()));
+        let mut maps = vec![];
+        for _ in 0..300 {
+            maps.push(
+                surfaces
+                    .get(id)
+                    .unwrap()
+                    .resource::<HexMap<MyTileData>>()
+                    .clone(),
+            );
+            step(&mut surfaces, 1);
+        }
+        let changed = snapshot::diff_tiles(&maps[0], maps.last().unwrap()).unwrap();
+        assert!(!changed.is_empty());
+        let history = surfaces.history(id).unwrap();
+        for (tick, map) in maps.iter().enumerate() {
+            let past = history.map_at(tick as u64).unwrap();
+            let diffs = snapshot::diff_tiles(&past, map).unwrap();
+            assert!(
+                diffs.is_empty(),
+                "tick {tick} differs at {:?}",
+                diffs[0].pos
+            );
+        }
+        assert!(history.map_at(301).is_none());
+    }
+
+    #[test]
+    fn rewinding_stops_at_a_failed_invariant() {
+        let (mut surfaces, id) = simulation::new_surfaces(SEED);
+        surfaces.set_history(id, Some(HistorySettings::default()));
+        step(&mut surfaces, 300);
+        surfaces.push_invariant("before tick 260", |world| {
+            match world.resource::<SurfaceTick>().0 < 260 {
+                true => Ok(()),
+                false => Err(Violation {
+                    message: "too late".to_owned(),
+                    tiles: vec![],
+                }),
+            }
+        });
+        assert!(surfaces.rewind_to(id, 290).unwrap());
+        assert_eq!(
+            surfaces.invariant_failure().map(|report| report.tick),
+            Some(260)
+        );
+        let tick = surfaces.get(id).unwrap().resource::<SurfaceTick>().0;
+        assert_eq!(tick, 261);
     }
 }
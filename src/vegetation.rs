@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    calendar::Calendar,
+    hexmap::{HexMap, HexPos},
+    rng::SurfaceRng,
+    schedule::SurfaceSystem,
+    simulation::{MyTileData, TileKind},
+    surfaces::{SurfaceTick, Surfaces},
+    wildfire::Fire,
+};
+
+/// Rock at or above this height is too exposed for anything to grow on
+pub const BARE_ROCK_HEIGHT: u8 = 5;
+
+const GROWTH_PER_TICK: f32 = 0.002;
+const DIE_OFF_PER_TICK: f32 = 0.01;
+/// Tiles need at least this much density before they start seeding their neighbours
+const SPREAD_THRESHOLD: f32 = 0.5;
+const SPREAD_CHANCE: f32 = 0.005;
+/// Chance for a bare but habitable tile to sprout on its own, so vegetation can start somewhere
+const GERMINATION_CHANCE: f32 = 0.0005;
+pub const SEEDLING_DENSITY: f32 = 0.05;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Inspectable, Serialize, Deserialize)]
+pub struct Vegetation {
+    /// `0.0..=1.0`
+    pub density: f32,
+    pub plant: PlantKind,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Inspectable, Serialize, Deserialize)]
+pub enum PlantKind {
+    #[default]
+    None,
+    Grass,
+    Shrub,
+    Forest,
+}
+
+impl PlantKind {
+    /// Which plant does best under the given conditions, `None` if nothing can grow at all
+    pub fn best_for(moisture: f32, temperature: f32) -> Self {
+        match (moisture, temperature) {
+            (m, t) if m <= 0.0 || t <= 0.0 => Self::None,
+            (m, t) if m > 0.5 && t > 0.5 => Self::Forest,
+            (m, _) if m > 0.25 => Self::Shrub,
+            _ => Self::Grass,
+        }
+    }
+
+    /// How much this plant holds the ground together, scaled by density in `MyTileData::erosion_resistance`
+    pub fn root_strength(self) -> f32 {
+        match self {
+            Self::None => 0.0,
+            Self::Grass => 0.3,
+            Self::Shrub => 0.6,
+            Self::Forest => 1.0,
+        }
+    }
+}
+
+/// Fraction of the tile's neighbours that are water, `0.0..=1.0`
+pub fn moisture(map: &HexMap<MyTileData>, pos: HexPos) -> f32 {
+    let wet = map
+        .wrapped_neighbors(pos)
+        .filter(|&pos| map.get(pos).kind == TileKind::Water)
+        .count();
+    wet as f32 / 6.0
+}
+
+/// Very rough temperature from altitude, `1.0` at sea level and `0.0` at `BARE_ROCK_HEIGHT`,
+/// shifted by `season_offset` (see `Calendar::temperature_offset`)
+pub fn temperature(tile: &MyTileData, season_offset: f32) -> f32 {
+    1.0 - (tile.height as f32 / BARE_ROCK_HEIGHT as f32).min(1.0) + season_offset
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_system(SurfaceSystem::new(grow_vegetation).when_dormant())
+        .push_metric("mean vegetation density", mean_density);
+}
+
+fn mean_density(map: Res<HexMap<MyTileData>>) -> f32 {
+    let total = map
+        .positions()
+        .map(|pos| map.get(pos).vegetation.density)
+        .sum::<f32>();
+    total / (map.width() * map.height()) as f32
+}
+
+fn grow_vegetation(
+    mut map: ResMut<HexMap<MyTileData>>,
+    mut rng: ResMut<SurfaceRng>,
+    calendar: Res<Calendar>,
+    tick: Res<SurfaceTick>,
+) {
+    let rng = rng.stream("vegetation");
+    let season_offset = calendar.temperature_offset(tick.0);
+    let growth = GROWTH_PER_TICK * calendar.date(tick.0).season.growth_factor();
+
+    // work out every tile's next state from the current map before writing anything so
+    // the result doesn't depend on iteration order
+    let mut next = Vec::with_capacity(map.width() * map.height());
+    for pos in map.positions() {
+        let tile = map.get(pos);
+        let mut vegetation = tile.vegetation;
+        // burning and ash tiles are handled by the wildfire system
+        if tile.fire != Fire::None {
+            continue;
+        }
+        // what grows is down to the climate, the season only decides whether it survives
+        let plant = PlantKind::best_for(moisture(&map, pos), temperature(tile, 0.0));
+
+        let dies = tile.kind == TileKind::Water
+            || tile.lava.is_molten()
+            || tile.height >= BARE_ROCK_HEIGHT
+            || temperature(tile, season_offset) <= 0.0
+            || plant == PlantKind::None;
+        if dies {
+            vegetation.density = (vegetation.density - DIE_OFF_PER_TICK).max(0.0);
+        } else if vegetation.density > 0.0 {
+            vegetation.density = (vegetation.density + growth).min(1.0);
+            vegetation.plant = plant;
+        } else {
+            let seeded = rng.chance(GERMINATION_CHANCE)
+                || map.wrapped_neighbors(pos).any(|pos| {
+                    map.get(pos).vegetation.density >= SPREAD_THRESHOLD && rng.chance(SPREAD_CHANCE)
+                });
+            if seeded {
+                vegetation = Vegetation {
+                    density: SEEDLING_DENSITY,
+                    plant,
+                };
+            }
+        }
+
+        if vegetation.density <= 0.0 {
+            vegetation = Vegetation::default();
+        }
+        next.push((pos, vegetation));
+    }
+
+    for (pos, vegetation) in next {
+        if map.get(pos).vegetation != vegetation {
+            map.get_mut(pos).vegetation = vegetation;
+        }
+    }
+}
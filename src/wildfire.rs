@@ -0,0 +1,187 @@
+use bevy::{math::vec2, prelude::*};
+use bevy_inspector_egui::Inspectable;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    draw::{Action, HoveredHex},
+    hexmap::{HexMap, HexPos},
+    rng::SurfaceRng,
+    schedule::SurfaceSystem,
+    simulation::MyTileData,
+    surfaces::{CurrentSurface, Surfaces},
+    vegetation::{self, PlantKind, Vegetation},
+    AppState,
+};
+
+const BURN_TICKS: u16 = 120;
+const ASH_TICKS: u16 = 1200;
+/// Per tick chance of a lightning strike etc. on a tile with full fuel
+const IGNITION_CHANCE: f32 = 0.000_01;
+/// Per tick chance of a burning tile spreading to a fully fuelled, bone dry neighbour with no wind
+const SPREAD_CHANCE: f32 = 0.02;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Inspectable, Serialize, Deserialize)]
+pub enum Fire {
+    #[default]
+    None,
+    Burning {
+        ticks_left: u16,
+    },
+    /// Burnt out, nothing grows until the ash clears
+    Ash {
+        ticks_left: u16,
+    },
+}
+
+impl Fire {
+    pub fn is_burning(self) -> bool {
+        matches!(self, Self::Burning { .. })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Inspectable, Serialize, Deserialize)]
+pub struct Wind {
+    /// Radians, in the same space as `hex_direction`
+    pub angle: f32,
+    /// `0.0` means spreading ignores the wind, `1.0` means fire can't spread directly upwind
+    pub strength: f32,
+}
+
+/// Hexes to set alight next tick, for user actions and scenario scripts
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Ignitions(pub Vec<HexPos>);
+
+/// How much there is to burn on the tile, `0.0..=1.0`
+pub fn fuel(tile: &MyTileData) -> f32 {
+    let flammability = match tile.vegetation.plant {
+        PlantKind::None => 0.0,
+        PlantKind::Grass => 0.6,
+        PlantKind::Shrub => 0.8,
+        PlantKind::Forest => 1.0,
+    };
+    tile.vegetation.density * flammability
+}
+
+/// Unit vector for a step between neighbouring hexes
+fn hex_direction(offset: HexPos) -> Vec2 {
+    vec2(
+        offset.q as f32 * 3f32.sqrt() / 2.0,
+        offset.q as f32 / 2.0 + offset.r as f32,
+    )
+}
+
+pub fn init_app(app: &mut App) {
+    app.add_system(ignite_hovered.run_in_state(AppState::Playing));
+}
+
+pub fn add_systems(surfaces: &mut Surfaces) {
+    surfaces
+        .push_world_init(|world| {
+            world.init_resource::<Wind>();
+            world.init_resource::<Ignitions>();
+        })
+        .push_state_resource::<Wind>()
+        .push_state_resource::<Ignitions>()
+        .push_system(SurfaceSystem::new(spread_fire).when_dormant())
+        .push_metric("burning tiles", burning_tiles);
+}
+
+fn burning_tiles(map: Res<HexMap<MyTileData>>) -> f32 {
+    map.positions()
+        .filter(|&pos| map.get(pos).fire.is_burning())
+        .count() as f32
+}
+
+fn ignite_hovered(
+    actions: Query<&ActionState<Action>, With<Camera>>,
+    hovered: Res<HoveredHex>,
+    mut surface: CurrentSurface<'_, '_>,
+) {
+    if !actions.single().just_pressed(Action::IgniteHex) {
+        return;
+    }
+    if let (Some(pos), Some(world)) = (hovered.0, surface.world_mut()) {
+        world.resource_mut::<Ignitions>().0.push(pos);
+    }
+}
+
+fn spread_fire(
+    mut map: ResMut<HexMap<MyTileData>>,
+    mut rng: ResMut<SurfaceRng>,
+    mut ignitions: ResMut<Ignitions>,
+    wind: Res<Wind>,
+) {
+    let rng = rng.stream("wildfire");
+    let wind_dir = Vec2::from_angle(wind.angle);
+
+    let mut next = Vec::with_capacity(map.width() * map.height());
+    for pos in map.positions() {
+        let tile = map.get(pos);
+        let fire = match tile.fire {
+            Fire::Burning { ticks_left: 0 } => Fire::Ash {
+                ticks_left: ASH_TICKS,
+            },
+            Fire::Burning { ticks_left } => Fire::Burning {
+                ticks_left: ticks_left - 1,
+            },
+            Fire::Ash { ticks_left: 0 } => Fire::None,
+            Fire::Ash { ticks_left } => Fire::Ash {
+                ticks_left: ticks_left - 1,
+            },
+            Fire::None => {
+                let fuel = fuel(tile);
+                let dryness = 1.0 - vegetation::moisture(&map, pos);
+                let caught = fuel > 0.0
+                    && (rng.chance(IGNITION_CHANCE * fuel)
+                        || pos.neighbors().any(|neighbor| {
+                            // the fire travels from `neighbor` towards `pos`
+                            let spread_dir = hex_direction(HexPos {
+                                q: pos.q - neighbor.q,
+                                r: pos.r - neighbor.r,
+                            });
+                            let wind_factor = 1.0 + wind.strength * spread_dir.dot(wind_dir);
+                            map.get(map.wrap(neighbor)).fire.is_burning()
+                                && rng.chance(SPREAD_CHANCE * fuel * dryness * wind_factor)
+                        }));
+                match caught {
+                    true => Fire::Burning {
+                        ticks_left: BURN_TICKS,
+                    },
+                    false => Fire::None,
+                }
+            }
+        };
+        next.push((pos, fire));
+    }
+
+    for pos in ignitions.0.drain(..) {
+        let pos = map.wrap(pos);
+        if fuel(map.get(pos)) > 0.0 {
+            next[pos.q as usize + pos.r as usize * map.width()].1 = Fire::Burning {
+                ticks_left: BURN_TICKS,
+            };
+        }
+    }
+
+    for (pos, fire) in next {
+        let tile = map.get(pos);
+        if tile.fire == fire {
+            continue;
+        }
+        let tile = map.get_mut(pos);
+        match fire {
+            Fire::Ash { .. } => tile.vegetation = Vegetation::default(),
+            // ash is good for the soil, let something sprout straight away
+            Fire::None => {
+                tile.vegetation = Vegetation {
+                    density: vegetation::SEEDLING_DENSITY,
+                    plant: PlantKind::Grass,
+                }
+            }
+            _ => (),
+        }
+        tile.fire = fire;
+    }
+}